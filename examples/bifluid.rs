@@ -1,9 +1,9 @@
 // resolution of the wave equation on a square
 // with the leapfrog method and minicl
 // you need python and matplotlib for seeing the results
-fn main() -> Result<(), minicl::MCLError> {
-    use std::fs;
+use minicl::{KernelSource, Precision};
 
+fn main() -> Result<(), minicl::MCLError> {
     // numerical parameters
     let nx = 512;
     let ny = 256;
@@ -25,19 +25,17 @@ fn main() -> Result<(), minicl::MCLError> {
     println!("grid size {}x{}", nx, ny);
 
     // tuning of the OpenCL sources
-    let mut source = fs::read_to_string("examples/bifluid_kernels.cl").unwrap();
-
-    source = source.replace("real", &"float");
-    source = source.replace("_float_", &"float");
-    source = source.replace("_F", &"f");
-    source = source.replace("_nx_", &nx.to_string());
-    source = source.replace("_ny_", &ny.to_string());
-    source = source.replace("_dx_", &dx.to_string());
-    source = source.replace("_dy_", &dy.to_string());
-    source = source.replace("_lambda_", &vmax.to_string());
-    source = source.replace("_dt_", &dt.to_string());
-    source = source.replace("_m_", &mw.to_string());
-    source = source.replace("_n_", &nk.to_string());
+    let source = KernelSource::from_file("examples/bifluid_kernels.cl")?
+        .precision(Precision::Single)
+        .set("nx", nx)
+        .set("ny", ny)
+        .set("dx", dx)
+        .set("dy", dy)
+        .set("lambda", vmax)
+        .set("dt", dt)
+        .set("m", mw)
+        .set("n", nk)
+        .render()?;
 
     // ask the platform id to the user
     use std::io::stdin;
@@ -78,10 +76,10 @@ fn main() -> Result<(), minicl::MCLError> {
         count += 1;
         minicl::kernel_set_args_and_run!(cldev, time_step, globsize, locsize, fnow, fnext)?;
         println!("tmax={} tend={}", tmax, t);
-        let temp = fnow;
-        fnow = fnext;
-        fnext = temp;
+        std::mem::swap(&mut fnow, &mut fnext);
     }
+    // wait for the queued time steps
+    cldev.finish()?;
 
     let duration = start.elapsed();
     println!("{} iters in {:?}", count, duration);
@@ -89,7 +87,7 @@ fn main() -> Result<(), minicl::MCLError> {
     println!("Plotting...");
     // get back the buffer on the host
     // for plotting
-    let fnow: Vec<f32> = cldev.map_buffer(fnow)?;
+    let fnow = cldev.map_read(fnow)?;
     let mut wnow: Vec<f32> = vec![0.; n];
 
     let iplot = 3;
@@ -1,18 +1,16 @@
 //#pragma OPENCL EXTENSION cl_khr_fp64 : enable
 
-#define real _real_
-#define _NX _nx_
-#define _NY _ny_
-#define _DX _dx_
-#define _DY _dy_
-#define _DT _dt_
-#define _M _m_
-#define _N _n_
-#define _VX _vx_
-#define _VY _vy_
-#define _NCPR _ncpr_
-
-#define _LAMBDA _lambda_
+// the real type and the parameters below
+// are set by minicl::KernelSource
+#define _NX ${nx}
+#define _NY ${ny}
+#define _DX ${dx}
+#define _DY ${dy}
+#define _DT ${dt}
+#define _M ${m}
+#define _N ${n}
+
+#define _LAMBDA ${lambda}
 
 #ifndef M_PI
 #define M_PI (3.14159265358979323846264338328)
@@ -22,8 +20,8 @@
 
 #define _C 10
 #define _RHO0 1.
-#define _LX (_nx_*_dx_)
-#define _LY (_ny_*_dy_)
+#define _LX (_NX*_DX)
+#define _LY (_NY*_DY)
 
 #define GRAVITY 9.81
 #define rhoAir 1
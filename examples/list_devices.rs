@@ -0,0 +1,24 @@
+// lists the OpenCL platforms and devices
+// available on this computer
+fn main() -> Result<(), minicl::MCLError> {
+    for platform in minicl::platforms()? {
+        let info = platform.info()?;
+        println!(
+            "Platform {}: {} ({}, {})",
+            info.index, info.name, info.vendor, info.version
+        );
+        for (i, device) in platform.devices()?.iter().enumerate() {
+            let info = device.info()?;
+            println!("  Device {}: {} ({})", i, info.name, info.vendor);
+            println!("    version:             {}", info.version);
+            println!("    driver version:      {}", info.driver_version);
+            println!("    compute units:       {}", info.compute_units);
+            println!("    max work-group size: {}", info.max_work_group_size);
+            println!("    max work-item sizes: {:?}", info.max_work_item_sizes);
+            println!("    global memory:       {} MB", info.global_mem_size >> 20);
+            println!("    local memory:        {} kB", info.local_mem_size >> 10);
+            println!("    double precision:    {}", info.fp64);
+        }
+    }
+    Ok(())
+}
@@ -4,10 +4,11 @@
 // http://hal.archives-ouvertes.fr/hal-00596730
 
 use minicl::Accel;
+use minicl::KernelSource;
 #[allow(unused_imports)]
 use minicl::LocalBuffer;
 use minicl::MCLError;
-use std::fs;
+use minicl::MemFlags;
 use std::io::stdin;
 use std::time::Instant;
 
@@ -54,26 +55,22 @@ fn main() -> Result<(), MCLError> {
     println!("Radix Sort OpenCL Example in Rust");
     println!("Reading OpenCL source...");
 
-    let mut source = fs::read_to_string("examples/radix_sort_kernel.cl")
-        .expect("Could not read examples/radix_sort_kernel.cl");
-
-    // Substitute parameters
-    source = source.replace("<ITEMS>", &ITEMS.to_string());
-    source = source.replace("<GROUPS>", &GROUPS.to_string());
-    source = source.replace("<HISTOSPLIT>", &HISTOSPLIT.to_string());
-    source = source.replace("<TOTALBITS>", &TOTALBITS.to_string());
-    source = source.replace("<BITS>", &BITS.to_string());
-    source = source.replace("<BITS>", &BITS.to_string());
-    source = source.replace("<N>", &N.to_string());
 
     // Calculate max local memory for scan
     let maxmemcache = std::cmp::max(HISTOSPLIT, (ITEMS * GROUPS * RADIX) / HISTOSPLIT);
     let max_loc_scan = maxmemcache; // This is the count of items
-    source = source.replace("<MAX_LOC_SCAN>", &max_loc_scan.to_string());
 
-    if USE_TRANSPOSE {
-        source.insert_str(0, "#define TRANSPOSE\n");
-    }
+    // Substitute parameters
+    let source = KernelSource::from_file("examples/radix_sort_kernel.cl")?
+        .set("ITEMS", ITEMS)
+        .set("GROUPS", GROUPS)
+        .set("HISTOSPLIT", HISTOSPLIT)
+        .set("TOTALBITS", TOTALBITS)
+        .set("BITS", BITS)
+        .set("N", N)
+        .set("MAX_LOC_SCAN", max_loc_scan)
+        .flag("TRANSPOSE", USE_TRANSPOSE)
+        .render()?;
 
     println!("Enter platform num:");
     let mut s = String::new();
@@ -106,8 +103,7 @@ fn main() -> Result<(), MCLError> {
     // d_inKeys
     let mut d_in_keys = cldev.register_buffer(h_keys)?;
     // d_outKeys
-    let d_out_keys_vec = vec![0u32; N];
-    let mut d_out_keys = cldev.register_buffer(d_out_keys_vec)?;
+    let mut d_out_keys = cldev.alloc_buffer::<u32>(N, MemFlags::READ_WRITE)?;
 
     // d_inPermut (0..N) - Used if PERMUT defined or in reorder args
     // Even if we don't verify permutation, the kernel signature might require it?
@@ -116,20 +112,18 @@ fn main() -> Result<(), MCLError> {
     let h_permut: Vec<u32> = (0..N as u32).collect();
     let mut d_in_permut = cldev.register_buffer(h_permut)?;
     // d_outPermut
-    let d_out_permut_vec = vec![0u32; N];
-    let mut d_out_permut = cldev.register_buffer(d_out_permut_vec)?;
+    let mut d_out_permut = cldev.alloc_buffer::<u32>(N, MemFlags::READ_WRITE)?;
 
     // Histograms
-    let h_histograms = vec![0u32; RADIX * GROUPS * ITEMS];
-    let d_histograms = cldev.register_buffer(h_histograms)?;
+    // (device only: never accessed from the host)
+    let d_histograms =
+        cldev.alloc_buffer_zeroed::<u32>(RADIX * GROUPS * ITEMS, MemFlags::READ_WRITE)?;
 
     // Global Sum
-    let h_globsum = vec![0u32; HISTOSPLIT];
-    let d_globsum = cldev.register_buffer(h_globsum)?;
+    let d_globsum = cldev.alloc_buffer_zeroed::<u32>(HISTOSPLIT, MemFlags::READ_WRITE)?;
 
     // Temp for scan
-    let d_temp_vec = vec![0u32; HISTOSPLIT];
-    let d_temp = cldev.register_buffer(d_temp_vec)?;
+    let d_temp = cldev.alloc_buffer_zeroed::<u32>(HISTOSPLIT, MemFlags::READ_WRITE)?;
 
     // Resize / Padding Logic (simplified: assuming N is valid as per C++ default)
     assert_eq!(N % (GROUPS * ITEMS), 0);
@@ -353,6 +347,7 @@ fn main() -> Result<(), MCLError> {
         std::mem::swap(&mut d_in_keys, &mut d_out_keys);
         std::mem::swap(&mut d_in_permut, &mut d_out_permut);
     }
+    cldev.finish()?;
 
     let duration = start_time.elapsed();
     println!("Sorting took: {:?}", duration);
@@ -360,7 +355,7 @@ fn main() -> Result<(), MCLError> {
     // Verify
     // The final result is in d_in_keys (because we swapped at end of loop)
     // Map back
-    let final_keys: Vec<u32> = cldev.map_buffer(d_in_keys)?;
+    let final_keys = cldev.map_read(d_in_keys)?;
     // let final_permut = cldev.map_buffer(d_in_permut)?;
 
     println!("Verifying order...");
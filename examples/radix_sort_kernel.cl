@@ -1,23 +1,23 @@
 // OpenCL kernels for sorting integer list
 // global parameters for the radix sort kernels
-// they are replaced by string manipulation before compilation
+// they are set by minicl::KernelSource before compilation
 ///////////////////////////////////////////////////////
 // number of items in a group
-#define _ITEMS <ITEMS>
+#define _ITEMS ${ITEMS}
 // the number of virtual processors is _ITEMS * _GROUPS
-#define _GROUPS <GROUPS>
+#define _GROUPS ${GROUPS}
 // number of splits of the histogram
-#define _HISTOSPLIT <HISTOSPLIT>
+#define _HISTOSPLIT ${HISTOSPLIT}
 // number of bits for the integer in the list (max=32)
-#define _TOTALBITS <TOTALBITS>
+#define _TOTALBITS ${TOTALBITS}
 // number of bits in the radix
-#define _BITS <BITS>
+#define _BITS ${BITS}
 // maximal size of the list
 // it has to be divisible by _ITEMS * _GROUPS
 // (for other sizes, pad the list with big values)
-#define _N <N>
+#define _N ${N}
 // max local memory for scan kernel
-#define _MAX_LOC_SCAN <MAX_LOC_SCAN>
+#define _MAX_LOC_SCAN ${MAX_LOC_SCAN}
 #define VERBOSE 1
 // transpose the initial vector (faster memory access)
 // #define TRANSPOSE
@@ -33,7 +33,7 @@
 #define _MAXINT (1 << (_TOTALBITS - 1))
 
 // OpenCL kernel sources
-// <ITEMS>, <GROUPS>, etc. are replaced by actual values before compilation
+// _ITEMS, _GROUPS, etc. are replaced by actual values before compilation
 
 // compute the histogram for each radix and each virtual processor for the pass
 // compute the histogram for each radix and each virtual processor for the pass
@@ -38,11 +38,11 @@ fn main() -> Result<(), minicl::MCLError> {
     minicl::kernel_set_args_and_run!(cldev, kname, globsize, locsize, v, x)?;
 
     // map the buffer for access from the host
-    let v: Vec<i32> = cldev.map_buffer(v)?;
-    println!("First kernel run v={:?}", v);
+    let mapped = cldev.map_buffer(v)?;
+    println!("First kernel run v={:?}", &mapped[..]);
 
     // unmap for giving it back to the device
-    let v = cldev.unmap_buffer(v)?;
+    mapped.unmap()?;
 
     // next call: no need to redefine the kernel args
     // if they are the same
@@ -53,12 +53,13 @@ fn main() -> Result<(), minicl::MCLError> {
         minicl::kernel_set_args_and_run!(cldev, kname, globsize, locsize, v, x)?;
         //unsafe { cldev.run_kernel(&kname, globsize, locsize) };
     }
+    // the kernels run asynchronously: wait for the last one
+    cldev.finish()?;
     let duration = start.elapsed();
 
     println!("Computing time: {:?}", duration);
 
-    //let v = cldev.unmap_buffer(v);
-    let v: Vec<i32> = cldev.map_buffer(v)?;
+    let v = cldev.map_buffer(v)?;
     println!("Next kernel run v={:?}", v[0]);
     Ok(())
 }
@@ -5,8 +5,6 @@
 // with the leapfrog method and minicl
 // you need python and matplotlib for seeing the results
 fn main() -> Result<(), minicl::MCLError> {
-    use std::fs;
-
     // numerical parameters
     let nx = 4096;
     let ny = 4096;
@@ -17,7 +15,7 @@ fn main() -> Result<(), minicl::MCLError> {
     let dx = lx / (nx - 1) as f32;
     let dy = ly / (ny - 1) as f32;
 
-    let cson: f32 = (1 as f32).sqrt();
+    let cson: f32 = 1_f32.sqrt();
 
     let cfl: f32 = 0.4;
 
@@ -26,17 +24,15 @@ fn main() -> Result<(), minicl::MCLError> {
     println!("grid size {}x{}",nx,ny);
 
     // tuning of the OpenCL sources
-    let mut source = fs::read_to_string("examples/wave2d_kernels.cl").unwrap();
-
-    source = source.replace("real", &"float");
-    source = source.replace("_float_", &"float");
-    source = source.replace("_F", &"f");
-    source = source.replace("_nx_", &nx.to_string());
-    source = source.replace("_ny_", &ny.to_string());
-    source = source.replace("_dx_", &dx.to_string());
-    source = source.replace("_dy_", &dy.to_string());
-    source = source.replace("_cson_", &cson.to_string());
-    source = source.replace("_dt_", &dt.to_string());
+    let options = minicl::BuildOptions::new()
+        .no_warnings()
+        .define("real", "float")
+        .define("_NX", nx)
+        .define("_NY", ny)
+        .define_f32("_DX", dx)
+        .define_f32("_DY", dy)
+        .define_f32("_C", cson)
+        .define_f32("_DT", dt);
 
     // ask the platform id to the user
     use std::io::stdin;
@@ -46,7 +42,10 @@ fn main() -> Result<(), minicl::MCLError> {
     let input: usize = s.trim().parse().unwrap();
     let numplat = input;
 
-    let mut cldev = minicl::Accel::new(source, numplat)?;
+    let mut cldev = minicl::Accel::builder()
+        .platform(numplat)
+        .options(options)
+        .build_from_files(&["examples/wave2d_kernels.cl"])?;
 
     // registration of the kernels
     let init_sol = "init_sol".to_string();
@@ -64,8 +63,9 @@ fn main() -> Result<(), minicl::MCLError> {
     let mut un = cldev.register_buffer(un)?;
     let mut unp1 = cldev.register_buffer(unp1)?;
 
-    let globsize = n;
-    let locsize = 32;
+    // 2D launch: one work-item per grid point
+    let globsize = [nx, ny];
+    let locsize = [32, 1];
 
     use std::time::Instant;
     let start = Instant::now();
@@ -85,6 +85,8 @@ fn main() -> Result<(), minicl::MCLError> {
         unp1 = temp;
     }
     println!("tmax={} tend={}", tmax, t);
+    // wait for the queued time steps
+    cldev.finish()?;
 
     let duration = start.elapsed();
     println!("{} iters in {:?}",count, duration);
@@ -92,7 +94,7 @@ fn main() -> Result<(), minicl::MCLError> {
     println!("Plotting...");
     // get back the buffer on the host
     // for plotting
-    let un: Vec<f32> = cldev.map_buffer(un)?;
+    let un: Vec<f32> = cldev.map_read(un)?.to_vec();
 
     let xp: Vec<f32> = (0..nx).map(|i| i as f32 * dx).collect();
     let yp: Vec<f32> = (0..ny).map(|i| i as f32 * dy).collect();
@@ -1,17 +1,13 @@
 
 //#pragma OPENCL EXTENSION cl_khr_fp64 : enable
 
-#define real _real_
+// _NX, _NY, _DX, _DY, _DT and the sound speed _C
+// are defined by the build options
+#ifndef real
+#define real float
+#endif
 
-#define _NX _nx_
-#define _NY _ny_
-#define _DX _dx_
-#define _DY _dy_
-#define _DT _dt_
-
-#define _C _cson_
-
-#define M_PI (3.14159265358979323846264338328_F)
+#define M_PI ((real)3.14159265358979323846264338328)
 
 
 #define _VOL (_DX * _DY)
@@ -21,7 +17,7 @@ __constant int dir[4][2] = { {-1, 0}, {1, 0},
 
 __constant real ds[4] = { _DY, _DY, _DX, _DX };
 
-#define _R (1._F)
+#define _R ((real)1)
 #define _G ((1 - _R) /(1 + _R))
 
 real peak(real x) {
@@ -78,10 +74,8 @@ void source(real *x, real t, real *s){
 // initial condition on the macro data
 __kernel void init_sol(__global real *un,__global real *unm1){
 
-  int id = get_global_id(0);
-  
-  int i = id % _NX;
-  int j = id / _NX;
+  int i = get_global_id(0);
+  int j = get_global_id(1);
 
   real unow;
 
@@ -115,10 +109,8 @@ __kernel void time_step(real tnow,
   real bx = _C * _DT / _DX; 
   real by = _C * _DT / _DY; 
 
-  int id = get_global_id(0);
-  
-  int i = id % _NX;
-  int j = id / _NX;
+  int i = get_global_id(0);
+  int j = get_global_id(1);
 
   real a = 1;
 
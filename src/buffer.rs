@@ -0,0 +1,1093 @@
+//! Memory buffers shared between the host and the device.
+use crate::event::{self, Event};
+use crate::{check_cl_error, Accel, MCLError, TrueArg};
+use std::alloc::Layout;
+use std::marker::PhantomData;
+
+/// Typed handle on a buffer registered in an [Accel].
+///
+/// The handle remembers the element type and the length of the buffer,
+/// and the [Accel] that owns it. It is a lightweight copyable value:
+/// the memory itself is managed by the [Accel].
+pub struct Buffer<T> {
+    id: usize,
+    accel: usize,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Buffer<T> {
+    /// Number of elements of the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the buffer has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T> Clone for Buffer<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Buffer<T> {}
+
+impl<T> PartialEq for Buffer<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.accel == other.accel
+    }
+}
+
+impl<T> Eq for Buffer<T> {}
+
+impl<T> std::fmt::Debug for Buffer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Buffer")
+            .field("id", &self.id)
+            .field("type", &std::any::type_name::<T>())
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+/// Plain data types, for which any bit pattern is a valid value:
+/// the integer and floating point types, and arrays of them.
+/// Only these can be read from memory that the host has not written,
+/// e.g. with [Accel::alloc_buffer] or [Accel::map_write_invalidate].
+/// The trait is sealed: it cannot be implemented outside MiniCL.
+/// ```compile_fail
+/// let mut cldev = minicl::Accel::new("__kernel void k(void){}".to_string(), 0)?;
+/// // a zero-filled reference would be null
+/// let refs = cldev.alloc_buffer_zeroed::<&u8>(4, minicl::MemFlags::READ_WRITE)?;
+/// # Ok::<(), minicl::MCLError>(())
+/// ```
+pub trait Pod: Copy + sealed::Sealed {}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+macro_rules! pod {
+    ($($t:ty),*) => {
+        $(impl sealed::Sealed for $t {}
+        impl Pod for $t {})*
+    }
+}
+
+pod!(i8, i16, i32, i64, u8, u16, u32, u64, isize, usize, f32, f64);
+
+impl<T: Pod, const N: usize> sealed::Sealed for [T; N] {}
+impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+/// Flags given at the creation of a buffer (`cl_mem_flags`).
+/// Flags can be combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemFlags(pub cl_sys::cl_mem_flags);
+
+impl MemFlags {
+    /// The buffer is read and written by the kernels (the default).
+    pub const READ_WRITE: MemFlags = MemFlags(cl_sys::CL_MEM_READ_WRITE);
+    /// The buffer is only read by the kernels.
+    pub const READ_ONLY: MemFlags = MemFlags(cl_sys::CL_MEM_READ_ONLY);
+    /// The buffer is only written by the kernels.
+    pub const WRITE_ONLY: MemFlags = MemFlags(cl_sys::CL_MEM_WRITE_ONLY);
+    /// The host only reads the buffer (maps and transfers).
+    pub const HOST_READ_ONLY: MemFlags = MemFlags(cl_sys::CL_MEM_HOST_READ_ONLY);
+    /// The host only writes the buffer (maps and transfers).
+    pub const HOST_WRITE_ONLY: MemFlags = MemFlags(cl_sys::CL_MEM_HOST_WRITE_ONLY);
+    /// The host neither reads nor writes the buffer.
+    pub const HOST_NO_ACCESS: MemFlags = MemFlags(cl_sys::CL_MEM_HOST_NO_ACCESS);
+    /// The memory is allocated by OpenCL in host accessible memory,
+    /// which may speed up the maps.
+    pub const ALLOC_HOST_PTR: MemFlags = MemFlags(cl_sys::CL_MEM_ALLOC_HOST_PTR);
+
+    /// Returns true if all the flags of `other` are in `self`.
+    pub fn contains(self, other: MemFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if the host is allowed to read the buffer.
+    pub fn host_readable(self) -> bool {
+        self.0 & (cl_sys::CL_MEM_HOST_WRITE_ONLY | cl_sys::CL_MEM_HOST_NO_ACCESS) == 0
+    }
+
+    /// Returns true if the host is allowed to write the buffer.
+    pub fn host_writable(self) -> bool {
+        self.0 & (cl_sys::CL_MEM_HOST_READ_ONLY | cl_sys::CL_MEM_HOST_NO_ACCESS) == 0
+    }
+}
+
+impl std::ops::BitOr for MemFlags {
+    type Output = MemFlags;
+    fn bitor(self, rhs: MemFlags) -> MemFlags {
+        MemFlags(self.0 | rhs.0)
+    }
+}
+
+/// Bookkeeping of a registered buffer.
+#[derive(Debug)]
+pub(crate) struct BufferEntry {
+    pub(crate) mem: cl_sys::cl_mem,
+    /// Host memory used by the buffer, owned by MiniCL.
+    /// None if the memory is allocated by OpenCL.
+    pub(crate) ptr: Option<*mut cl_sys::c_void>,
+    /// Size in bytes.
+    pub(crate) size: usize,
+    /// Host pointer of the current mapping, if the buffer is mapped.
+    pub(crate) mapped: Option<*mut cl_sys::c_void>,
+    pub(crate) layout: Layout,
+    pub(crate) flags: MemFlags,
+    /// Buffer containing this sub-buffer.
+    pub(crate) parent: Option<usize>,
+    /// Offset in bytes in the parent buffer.
+    pub(crate) origin: usize,
+}
+
+impl BufferEntry {
+    /// Checks that the access flags allow the host to read and/or write
+    /// the buffer.
+    fn check_host_access(&self, read: bool, write: bool) -> Result<(), MCLError> {
+        if read && !self.flags.host_readable() {
+            return Err(MCLError::BufferAccess(
+                "the host cannot read this buffer".to_string(),
+            ));
+        }
+        if write && !self.flags.host_writable() {
+            return Err(MCLError::BufferAccess(
+                "the host cannot write this buffer".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Enqueues the unmap of the current mapping.
+    pub(crate) fn unmap(&mut self, queue: cl_sys::cl_command_queue) -> Result<(), MCLError> {
+        let ptr = self.mapped.ok_or(MCLError::BufferNotMapped)?;
+        if self.mem.is_null() {
+            self.mapped = None;
+            return Ok(());
+        }
+        let err = unsafe {
+            cl_sys::clEnqueueUnmapMemObject(
+                queue,
+                self.mem,
+                ptr,
+                0,
+                std::ptr::null(),
+                std::ptr::null_mut(),
+            )
+        };
+        check_cl_error(err, "clEnqueueUnmapMemObject")?;
+        self.mapped = None;
+        Ok(())
+    }
+
+    /// Releases the OpenCL memory object.
+    pub(crate) fn release_mem(&self) {
+        if self.mem.is_null() {
+            return;
+        }
+        let err = unsafe { cl_sys::clReleaseMemObject(self.mem) };
+        assert_eq!(err, cl_sys::CL_SUCCESS, "{}", crate::error_text(err));
+    }
+
+    /// Frees the host memory owned by MiniCL, if any.
+    pub(crate) fn free_host(&self) {
+        if let Some(ptr) = self.ptr {
+            log::debug!("Free buffer host memory {:?}", ptr);
+
+            // Correctly deallocate memory using the stored layout
+            unsafe {
+                std::alloc::dealloc(ptr as *mut u8, self.layout);
+            }
+        }
+    }
+}
+
+impl Accel {
+    /// Registers a buffer before it can be passed to a kernel.
+    /// The buffer is automatically copied on the device. The memory is
+    /// managed by OpenCL until the next map.
+    /// It can not be accessed by the host until the next map.
+    /// Returns a typed handle on the buffer.
+    pub fn register_buffer<T>(&mut self, v: Vec<T>) -> Result<Buffer<T>, MCLError> {
+        self.register_buffer_with(v, MemFlags::READ_WRITE)
+    }
+
+    /// Same as [Accel::register_buffer], with the given access flags.
+    /// The flags are passed to OpenCL, and MiniCL refuses the host
+    /// accesses they forbid.
+    /// # Examples
+    /// ```no_run
+    /// # let mut cldev = minicl::Accel::new("".to_string(), 0)?;
+    /// use minicl::MemFlags;
+    /// // coefficients that the kernels and the host never modify
+    /// let coefs = cldev.register_buffer_with(
+    ///     vec![0.5f32; 9],
+    ///     MemFlags::READ_ONLY | MemFlags::HOST_READ_ONLY,
+    /// )?;
+    /// assert!(cldev.map_buffer(coefs).is_err());
+    /// # Ok::<(), minicl::MCLError>(())
+    /// ```
+    pub fn register_buffer_with<T>(
+        &mut self,
+        mut v: Vec<T>,
+        flags: MemFlags,
+    ) -> Result<Buffer<T>, MCLError> {
+        v.shrink_to_fit();
+        // the memory must be freed with the layout of the allocation,
+        // which is the layout of `capacity` elements
+        // (zero-sized types do not allocate and have an infinite capacity)
+        if v.len() != v.capacity() && std::mem::size_of::<T>() != 0 {
+            return Err(MCLError::SizeMismatch {
+                expected: v.len(),
+                found: v.capacity(),
+            });
+        }
+        let buf = if std::mem::size_of_val(v.as_slice()) == 0 {
+            // no allocation: nothing to share with OpenCL
+            self.create_buffer(v.len(), flags, None)?
+        } else {
+            // the vector is moved in: its allocation cannot be registered twice
+            let ptr0 = v.as_mut_ptr() as *mut cl_sys::c_void;
+            let flags = flags | MemFlags(cl_sys::CL_MEM_USE_HOST_PTR);
+            self.create_buffer(v.len(), flags, Some(ptr0))?
+        };
+        // leave deallocation duty to MiniCL
+        std::mem::forget(v);
+        Ok(buf)
+    }
+
+    /// Creates a buffer of `len` elements allocated by OpenCL,
+    /// without host memory managed by MiniCL.
+    /// The content of the buffer is unspecified: it has to be written
+    /// by a kernel, a transfer or a map before being read, hence the
+    /// [Pod] bound.
+    pub fn alloc_buffer<T: Pod>(
+        &mut self,
+        len: usize,
+        flags: MemFlags,
+    ) -> Result<Buffer<T>, MCLError> {
+        self.create_buffer(len, flags, None)
+    }
+
+    /// Same as [Accel::alloc_buffer], but the buffer is filled with zeros.
+    pub fn alloc_buffer_zeroed<T: Pod>(
+        &mut self,
+        len: usize,
+        flags: MemFlags,
+    ) -> Result<Buffer<T>, MCLError> {
+        let buf = self.alloc_buffer(len, flags)?;
+        let entry = &self.buffers[&buf.id];
+        // the in-order queue runs the fill before the next commands
+        self.enqueue_fill(entry.mem, &0u8, 0, entry.size, &[])?;
+        Ok(buf)
+    }
+
+    /// Creates the OpenCL buffer and registers it.
+    /// OpenCL does not support empty buffers: a buffer of zero bytes has
+    /// a null memory object, which kernels receive as a null pointer.
+    fn create_buffer<T>(
+        &mut self,
+        n: usize,
+        flags: MemFlags,
+        ptr: Option<*mut cl_sys::c_void>,
+    ) -> Result<Buffer<T>, MCLError> {
+        let layout = Layout::array::<T>(n)
+            .map_err(|_| MCLError::Other(format!("Buffer of {} elements too large", n)))?;
+        let mut buffer: cl_sys::cl_mem = std::ptr::null_mut();
+        if layout.size() > 0 {
+            let mut err: i32 = 0;
+            buffer = unsafe {
+                cl_sys::clCreateBuffer(
+                    self.context,
+                    flags.0,
+                    layout.size(),
+                    ptr.unwrap_or(std::ptr::null_mut()),
+                    &mut err,
+                )
+            };
+            check_cl_error(err, "clCreateBuffer")?;
+        }
+        let id = self.next_buffer;
+        self.next_buffer += 1;
+        self.buffers.insert(
+            id,
+            BufferEntry {
+                mem: buffer,
+                ptr,
+                size: layout.size(),
+                mapped: None,
+                layout,
+                flags,
+                parent: None,
+                origin: 0,
+            },
+        );
+        Ok(Buffer {
+            id,
+            accel: self.id,
+            len: n,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Creates a view on the elements `range` of a buffer
+    /// (`clCreateSubBuffer`). The view shares the memory of the
+    /// buffer and can be passed to the kernels as any buffer.
+    /// The start of the range must respect the alignment of the device
+    /// (see [Device::mem_base_addr_align](crate::Device::mem_base_addr_align)).
+    /// A mapped buffer blocks the use of its overlapping sub-buffers,
+    /// and conversely.
+    pub fn sub_buffer<T>(
+        &mut self,
+        buf: &Buffer<T>,
+        range: std::ops::Range<usize>,
+    ) -> Result<Buffer<T>, MCLError> {
+        let entry = self.buffer_entry(buf)?;
+        check_range(&range, buf.len)?;
+        let szf = std::mem::size_of::<T>();
+        let len = range.end - range.start;
+        // sub-buffers of sub-buffers are created in the root buffer
+        let parent = entry.parent.unwrap_or(buf.id);
+        let origin = entry.origin + range.start * szf;
+        let root = &self.buffers[&parent];
+        let mut mem: cl_sys::cl_mem = std::ptr::null_mut();
+        if len * szf > 0 {
+            let align = self.device.mem_base_addr_align()? as usize / 8;
+            if align > 0 && !origin.is_multiple_of(align) {
+                return Err(MCLError::Cl {
+                    code: cl_sys::CL_MISALIGNED_SUB_BUFFER_OFFSET,
+                    func: "clCreateSubBuffer",
+                    context: format!(
+                        "offset of {} bytes, the device requires a multiple of {} bytes",
+                        origin, align
+                    ),
+                });
+            }
+            let region = cl_sys::cl_buffer_region {
+                origin,
+                size: len * szf,
+            };
+            let mut err: i32 = 0;
+            mem = unsafe {
+                cl_sys::clCreateSubBuffer(
+                    root.mem,
+                    0,
+                    cl_sys::CL_BUFFER_CREATE_TYPE_REGION,
+                    &region as *const _ as *const cl_sys::c_void,
+                    &mut err,
+                )
+            };
+            check_cl_error(err, "clCreateSubBuffer")?;
+        }
+        let entry = BufferEntry {
+            mem,
+            ptr: None,
+            size: len * szf,
+            mapped: None,
+            layout: Layout::array::<T>(len).unwrap(),
+            flags: root.flags,
+            parent: Some(parent),
+            origin,
+        };
+        let id = self.next_buffer;
+        self.next_buffer += 1;
+        self.buffers.insert(id, entry);
+        Ok(Buffer {
+            id,
+            accel: self.id,
+            len,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Checks that neither the buffer nor an overlapping buffer of the
+    /// same memory (parent or sub-buffer) is mapped.
+    pub(crate) fn check_unmapped(&self, id: usize) -> Result<(), MCLError> {
+        let entry = &self.buffers[&id];
+        let root = entry.parent.unwrap_or(id);
+        let (start, end) = (entry.origin, entry.origin + entry.size);
+        let mapped = self.buffers.iter().any(|(&i, e)| {
+            e.mapped.is_some()
+                && (i == root || e.parent == Some(root))
+                && (i == id || (e.origin < end && start < e.origin + e.size))
+        });
+        if mapped {
+            return Err(MCLError::BufferMapped);
+        }
+        Ok(())
+    }
+
+    /// Returns the bookkeeping of a buffer, after checking that
+    /// it belongs to this [Accel].
+    pub(crate) fn buffer_entry<T>(&self, buf: &Buffer<T>) -> Result<&BufferEntry, MCLError> {
+        if buf.accel != self.id {
+            return Err(MCLError::ForeignBuffer);
+        }
+        self.buffers
+            .get(&buf.id)
+            .ok_or(MCLError::BufferNotRegistered)
+    }
+
+    /// Maps a buffer from the device to the host, for reading and writing.
+    /// Must be called before any access to the buffer
+    /// from the host side.
+    /// The returned guard gives access to the data as a slice. It borrows
+    /// the [Accel], so that no kernel can run while the buffer is mapped,
+    /// and gives the buffer back to the device when dropped.
+    /// The map waits for all the enqueued commands: the call blocks until
+    /// the [pending user events](Accel::user_event) are completed.
+    pub fn map_buffer<T>(&mut self, buf: Buffer<T>) -> Result<MappedBuffer<'_, T>, MCLError> {
+        self.map_buffer_after(buf, &[])
+    }
+
+    /// Same as [map_buffer](Accel::map_buffer), but the map waits for the
+    /// end of the commands of the wait list. The call blocks until then:
+    /// its user events must be completed by another thread.
+    pub fn map_buffer_after<T>(
+        &mut self,
+        buf: Buffer<T>,
+        wait_list: &[&Event],
+    ) -> Result<MappedBuffer<'_, T>, MCLError> {
+        self.map_with(buf, cl_sys::CL_MAP_READ | cl_sys::CL_MAP_WRITE, wait_list)
+    }
+
+    /// Maps a buffer for reading only.
+    /// The data is not sent back to the device at unmap, which saves a
+    /// transfer when the results are only inspected.
+    /// As [map_buffer](Accel::map_buffer), it blocks behind the
+    /// [pending user events](Accel::user_event).
+    pub fn map_read<T>(&mut self, buf: Buffer<T>) -> Result<ReadMappedBuffer<'_, T>, MCLError> {
+        self.map_read_after(buf, &[])
+    }
+
+    /// Same as [map_read](Accel::map_read), with a wait list
+    /// (see [map_buffer_after](Accel::map_buffer_after)).
+    pub fn map_read_after<T>(
+        &mut self,
+        buf: Buffer<T>,
+        wait_list: &[&Event],
+    ) -> Result<ReadMappedBuffer<'_, T>, MCLError> {
+        self.map_with(buf, cl_sys::CL_MAP_READ, wait_list)
+            .map(ReadMappedBuffer)
+    }
+
+    /// Maps a buffer for overwriting it entirely
+    /// (`CL_MAP_WRITE_INVALIDATE_REGION`).
+    /// The device data is not copied to the host: the content of the
+    /// slice is unspecified until it is written, hence the [Pod] bound.
+    pub fn map_write_invalidate<T: Pod>(
+        &mut self,
+        buf: Buffer<T>,
+    ) -> Result<MappedBuffer<'_, T>, MCLError> {
+        self.map_write_invalidate_after(buf, &[])
+    }
+
+    /// Same as [map_write_invalidate](Accel::map_write_invalidate), with a
+    /// wait list (see [map_buffer_after](Accel::map_buffer_after)).
+    pub fn map_write_invalidate_after<T: Pod>(
+        &mut self,
+        buf: Buffer<T>,
+        wait_list: &[&Event],
+    ) -> Result<MappedBuffer<'_, T>, MCLError> {
+        self.map_with(buf, cl_sys::CL_MAP_WRITE_INVALIDATE_REGION, wait_list)
+    }
+
+    /// Maps a buffer with the given map flags, after the wait list.
+    fn map_with<T>(
+        &mut self,
+        buf: Buffer<T>,
+        flags: cl_sys::cl_map_flags,
+        wait_list: &[&Event],
+    ) -> Result<MappedBuffer<'_, T>, MCLError> {
+        let mut err = 0;
+        let blocking = cl_sys::CL_TRUE;
+        let read = flags & cl_sys::CL_MAP_READ != 0;
+        let write = flags & (cl_sys::CL_MAP_WRITE | cl_sys::CL_MAP_WRITE_INVALIDATE_REGION) != 0;
+        self.buffer_entry(&buf)?.check_host_access(read, write)?;
+        self.check_unmapped(buf.id)?;
+        let mem = self.buffers[&buf.id].mem;
+        if mem.is_null() && !wait_list.is_empty() {
+            self.marker(wait_list)?.wait()?;
+        }
+        let events = event::raw_events(wait_list);
+        let entry = self.buffers.get_mut(&buf.id).unwrap();
+
+        let ptr = if entry.mem.is_null() {
+            // empty buffer: nothing to map
+            std::ptr::NonNull::<T>::dangling().as_ptr()
+        } else {
+            let ptr = unsafe {
+                cl_sys::clEnqueueMapBuffer(
+                    self.queue,
+                    entry.mem,
+                    blocking,
+                    flags,
+                    0,
+                    entry.size,
+                    events.len() as u32,
+                    event::events_ptr(&events),
+                    std::ptr::null_mut(),
+                    &mut err,
+                )
+            };
+            check_cl_error(err, "clEnqueueMapBuffer")?;
+            ptr as *mut T
+        };
+        entry.mapped = Some(ptr as *mut cl_sys::c_void);
+        let guard = MappedBuffer {
+            accel: self,
+            buffer: buf,
+            ptr,
+            mapped: true,
+        };
+
+        let host = guard.accel.buffers[&buf.id].ptr;
+        if host.is_some_and(|host| ptr != host as *mut T) {
+            // strict internal consistency check
+            return Err(MCLError::Other(
+                "OpenCL returned a different pointer than the hosted one. \
+                 MiniCL logic broken for CL_MEM_USE_HOST_PTR."
+                    .to_string(),
+            ));
+        }
+        Ok(guard)
+    }
+
+    /// Copies `data` into the buffer, starting at element `offset`.
+    /// Only the range `offset..offset + data.len()` is transferred.
+    /// The call returns when the copy is complete, hence after the
+    /// previous commands and the [pending user events](Accel::user_event).
+    pub fn write_buffer<T: Copy>(
+        &mut self,
+        buf: &Buffer<T>,
+        data: &[T],
+        offset: usize,
+    ) -> Result<(), MCLError> {
+        self.write_buffer_after(buf, data, offset, &[]).map(drop)
+    }
+
+    /// Same as [write_buffer](Accel::write_buffer), but the transfer waits
+    /// for the end of the commands of the wait list. The call blocks until
+    /// the transfer is complete: the user events of the wait list must be
+    /// completed by another thread. The returned [Event] is complete.
+    pub fn write_buffer_after<T: Copy>(
+        &mut self,
+        buf: &Buffer<T>,
+        data: &[T],
+        offset: usize,
+        wait_list: &[&Event],
+    ) -> Result<Event, MCLError> {
+        let mem = self.transfer_mem(buf, offset, data.len(), true)?;
+        if data.is_empty() || mem.is_null() {
+            let event = self.marker(wait_list)?;
+            event.wait()?;
+            return Ok(event);
+        }
+        let events = event::raw_events(wait_list);
+        let mut event: cl_sys::cl_event = std::ptr::null_mut();
+        let err = unsafe {
+            cl_sys::clEnqueueWriteBuffer(
+                self.queue,
+                mem,
+                cl_sys::CL_TRUE,
+                offset * std::mem::size_of::<T>(),
+                std::mem::size_of_val(data),
+                data.as_ptr() as *const cl_sys::c_void,
+                events.len() as u32,
+                event::events_ptr(&events),
+                &mut event,
+            )
+        };
+        check_cl_error(err, "clEnqueueWriteBuffer")?;
+        Ok(Event { event })
+    }
+
+    /// Copies the buffer elements, starting at `offset`, into `data`.
+    /// Only the range `offset..offset + data.len()` is transferred.
+    /// Like [write_buffer](Accel::write_buffer), the call blocks until the
+    /// transfer is complete.
+    pub fn read_buffer<T: Copy>(
+        &mut self,
+        buf: &Buffer<T>,
+        data: &mut [T],
+        offset: usize,
+    ) -> Result<(), MCLError> {
+        self.read_buffer_after(buf, data, offset, &[]).map(drop)
+    }
+
+    /// Same as [read_buffer](Accel::read_buffer), with a wait list
+    /// (see [write_buffer_after](Accel::write_buffer_after)).
+    pub fn read_buffer_after<T: Copy>(
+        &mut self,
+        buf: &Buffer<T>,
+        data: &mut [T],
+        offset: usize,
+        wait_list: &[&Event],
+    ) -> Result<Event, MCLError> {
+        let mem = self.transfer_mem(buf, offset, data.len(), false)?;
+        if data.is_empty() || mem.is_null() {
+            let event = self.marker(wait_list)?;
+            event.wait()?;
+            return Ok(event);
+        }
+        let events = event::raw_events(wait_list);
+        let mut event: cl_sys::cl_event = std::ptr::null_mut();
+        let err = unsafe {
+            cl_sys::clEnqueueReadBuffer(
+                self.queue,
+                mem,
+                cl_sys::CL_TRUE,
+                offset * std::mem::size_of::<T>(),
+                std::mem::size_of_val(data),
+                data.as_mut_ptr() as *mut cl_sys::c_void,
+                events.len() as u32,
+                event::events_ptr(&events),
+                &mut event,
+            )
+        };
+        check_cl_error(err, "clEnqueueReadBuffer")?;
+        Ok(Event { event })
+    }
+
+    /// Copies the elements `src_range` of `src` into `dst`, starting at
+    /// element `dst_offset`. The copy is done by the device.
+    /// The two regions must not overlap, which can only happen in the
+    /// same buffer or in sub-buffers of the same buffer.
+    /// The elements are duplicated bitwise, hence the `Copy` bound.
+    /// The copy is only enqueued, unless [set_blocking](Accel::set_blocking)
+    /// has been called: the returned [Event] tells when it is complete.
+    pub fn copy_buffer<T: Copy>(
+        &mut self,
+        src: &Buffer<T>,
+        dst: &Buffer<T>,
+        src_range: std::ops::Range<usize>,
+        dst_offset: usize,
+    ) -> Result<Event, MCLError> {
+        self.copy_buffer_after(src, dst, src_range, dst_offset, &[])
+    }
+
+    /// Same as [copy_buffer](Accel::copy_buffer), but the copy does not
+    /// start before the end of the commands of the wait list.
+    pub fn copy_buffer_after<T: Copy>(
+        &mut self,
+        src: &Buffer<T>,
+        dst: &Buffer<T>,
+        src_range: std::ops::Range<usize>,
+        dst_offset: usize,
+        wait_list: &[&Event],
+    ) -> Result<Event, MCLError> {
+        let (src_entry, dst_entry) = (self.buffer_entry(src)?, self.buffer_entry(dst)?);
+        check_range(&src_range, src.len)?;
+        let len = src_range.end - src_range.start;
+        check_range(&(dst_offset..dst_offset.saturating_add(len)), dst.len)?;
+        self.check_unmapped(src.id)?;
+        self.check_unmapped(dst.id)?;
+        let szf = std::mem::size_of::<T>();
+        let size = len * szf;
+        let src_start = src_entry.origin + src_range.start * szf;
+        let dst_start = dst_entry.origin + dst_offset * szf;
+        let same_memory = src_entry.parent.unwrap_or(src.id) == dst_entry.parent.unwrap_or(dst.id);
+        if same_memory && src_start < dst_start + size && dst_start < src_start + size {
+            return Err(MCLError::Cl {
+                code: cl_sys::CL_MEM_COPY_OVERLAP,
+                func: "clEnqueueCopyBuffer",
+                context: format!(
+                    "elements {}..{} copied to {}..{}",
+                    src_range.start,
+                    src_range.end,
+                    dst_offset,
+                    dst_offset + len
+                ),
+            });
+        }
+        let (src_mem, dst_mem) = (src_entry.mem, dst_entry.mem);
+        self.record_gates(wait_list)?;
+        if size == 0 {
+            return self.marker(wait_list);
+        }
+        self.enqueue_copy(
+            src_mem,
+            dst_mem,
+            src_range.start * szf,
+            dst_offset * szf,
+            size,
+            wait_list,
+        )
+    }
+
+    /// Enqueues the copy of a memory region, which must not overlap
+    /// the destination region.
+    fn enqueue_copy(
+        &self,
+        src: cl_sys::cl_mem,
+        dst: cl_sys::cl_mem,
+        src_offset: usize,
+        dst_offset: usize,
+        size: usize,
+        wait_list: &[&Event],
+    ) -> Result<Event, MCLError> {
+        let events = event::raw_events(wait_list);
+        let mut event: cl_sys::cl_event = std::ptr::null_mut();
+        let err = unsafe {
+            cl_sys::clEnqueueCopyBuffer(
+                self.queue,
+                src,
+                dst,
+                src_offset,
+                dst_offset,
+                size,
+                events.len() as u32,
+                event::events_ptr(&events),
+                &mut event,
+            )
+        };
+        check_cl_error(err, "clEnqueueCopyBuffer")?;
+        self.complete(Event { event })
+    }
+
+    /// Sets the elements `range` of a buffer to `value`.
+    /// The fill is done by the device, whatever the host access flags
+    /// of the buffer: with `clEnqueueFillBuffer` when the size of `T` is a
+    /// power of two up to 128 bytes, and otherwise by copying one element
+    /// from a temporary buffer, then doubling the filled region.
+    /// As for [copy_buffer](Accel::copy_buffer), the fill is only enqueued.
+    pub fn fill_buffer<T: Copy>(
+        &mut self,
+        buf: &Buffer<T>,
+        value: T,
+        range: std::ops::Range<usize>,
+    ) -> Result<Event, MCLError> {
+        self.fill_buffer_after(buf, value, range, &[])
+    }
+
+    /// Same as [fill_buffer](Accel::fill_buffer), but the fill does not
+    /// start before the end of the commands of the wait list.
+    pub fn fill_buffer_after<T: Copy>(
+        &mut self,
+        buf: &Buffer<T>,
+        value: T,
+        range: std::ops::Range<usize>,
+        wait_list: &[&Event],
+    ) -> Result<Event, MCLError> {
+        let mem = self.buffer_entry(buf)?.mem;
+        check_range(&range, buf.len)?;
+        self.check_unmapped(buf.id)?;
+        self.record_gates(wait_list)?;
+        let szf = std::mem::size_of::<T>();
+        let offset = range.start * szf;
+        let size = (range.end - range.start) * szf;
+        if szf.is_power_of_two() && szf <= 128 {
+            self.enqueue_fill(mem, &value, offset, size, wait_list)
+        } else {
+            self.enqueue_fill_by_copies(mem, &value, offset, size, wait_list)
+        }
+    }
+
+    /// Enqueues the fill of a memory region with a pattern, whose size
+    /// must be a power of two up to 128 bytes.
+    /// The pattern is read by OpenCL through a raw pointer, since its
+    /// padding bytes (if any) are uninitialized.
+    fn enqueue_fill<P: Copy>(
+        &self,
+        mem: cl_sys::cl_mem,
+        pattern: &P,
+        offset: usize,
+        size: usize,
+        wait_list: &[&Event],
+    ) -> Result<Event, MCLError> {
+        if size == 0 || mem.is_null() {
+            return self.marker(wait_list);
+        }
+        let events = event::raw_events(wait_list);
+        let mut event: cl_sys::cl_event = std::ptr::null_mut();
+        let err = unsafe {
+            cl_sys::clEnqueueFillBuffer(
+                self.queue,
+                mem,
+                pattern as *const P as *const cl_sys::c_void,
+                std::mem::size_of::<P>(),
+                offset,
+                size,
+                events.len() as u32,
+                event::events_ptr(&events),
+                &mut event,
+            )
+        };
+        check_cl_error(err, "clEnqueueFillBuffer")?;
+        self.complete(Event { event })
+    }
+
+    /// Fills a memory region with a value of any size: the value is copied
+    /// from a temporary buffer to the start of the region, then the filled
+    /// part is copied after itself until the region is full.
+    /// The in-order queue runs the copies one after the other.
+    fn enqueue_fill_by_copies<T: Copy>(
+        &self,
+        mem: cl_sys::cl_mem,
+        value: &T,
+        offset: usize,
+        size: usize,
+        wait_list: &[&Event],
+    ) -> Result<Event, MCLError> {
+        if size == 0 || mem.is_null() {
+            return self.marker(wait_list);
+        }
+        let szf = std::mem::size_of::<T>();
+        let mut err: i32 = 0;
+        let seed = unsafe {
+            cl_sys::clCreateBuffer(
+                self.context,
+                cl_sys::CL_MEM_READ_ONLY | cl_sys::CL_MEM_COPY_HOST_PTR,
+                szf,
+                value as *const T as *mut cl_sys::c_void,
+                &mut err,
+            )
+        };
+        check_cl_error(err, "clCreateBuffer")?;
+        let first = self.enqueue_copy(seed, mem, 0, offset, szf, wait_list);
+        // OpenCL keeps the temporary buffer until the end of the copy
+        unsafe { cl_sys::clReleaseMemObject(seed) };
+        let mut event = first?;
+        let mut filled = szf;
+        while filled < size {
+            let len = filled.min(size - filled);
+            event = self.enqueue_copy(mem, mem, offset, offset + filled, len, &[])?;
+            filled += len;
+        }
+        Ok(event)
+    }
+
+    /// Checks that a range of a buffer can be transferred and returns
+    /// the OpenCL memory object.
+    fn transfer_mem<T>(
+        &self,
+        buf: &Buffer<T>,
+        offset: usize,
+        len: usize,
+        write: bool,
+    ) -> Result<cl_sys::cl_mem, MCLError> {
+        let entry = self.buffer_entry(buf)?;
+        entry.check_host_access(!write, write)?;
+        self.check_unmapped(buf.id)?;
+        if offset.checked_add(len).is_none_or(|end| end > buf.len) {
+            return Err(MCLError::OutOfRange {
+                offset,
+                len,
+                buffer_len: buf.len,
+            });
+        }
+        Ok(entry.mem)
+    }
+
+    /// Unregisters a buffer and frees its device and host memory.
+    /// The handle, and its copies, can no longer be used.
+    /// The buffer is released after the end of the enqueued commands:
+    /// the call blocks until the [pending user events](Accel::user_event)
+    /// are completed.
+    pub fn release_buffer<T>(&mut self, buf: Buffer<T>) -> Result<(), MCLError> {
+        let entry = self.take_buffer(&buf)?;
+        entry.free_host();
+        Ok(())
+    }
+
+    /// Unregisters a buffer and gives its content back as a vector.
+    /// The device memory is released. For a buffer registered with
+    /// [Accel::register_buffer] the vector reuses the host memory,
+    /// without copy.
+    /// Like [release_buffer](Accel::release_buffer), it waits for the
+    /// enqueued commands and the [pending user events](Accel::user_event).
+    pub fn into_vec<T>(&mut self, buf: Buffer<T>) -> Result<Vec<T>, MCLError> {
+        self.buffer_entry(&buf)?.check_host_access(true, false)?;
+        if self.buffers[&buf.id].ptr.is_some() {
+            // synchronize the host memory with the device
+            self.map_read(buf)?.unmap()?;
+            let entry = self.take_buffer(&buf)?;
+            let ptr = entry.ptr.unwrap() as *mut T;
+            return Ok(unsafe { Vec::from_raw_parts(ptr, buf.len, buf.len) });
+        }
+        let mut v = Vec::with_capacity(buf.len);
+        let entry = &self.buffers[&buf.id];
+        if !entry.mem.is_null() {
+            let err = unsafe {
+                cl_sys::clEnqueueReadBuffer(
+                    self.queue,
+                    entry.mem,
+                    cl_sys::CL_TRUE,
+                    0,
+                    entry.size,
+                    v.as_mut_ptr() as *mut cl_sys::c_void,
+                    0,
+                    std::ptr::null(),
+                    std::ptr::null_mut(),
+                )
+            };
+            check_cl_error(err, "clEnqueueReadBuffer")?;
+        }
+        self.take_buffer(&buf)?;
+        unsafe { v.set_len(buf.len) };
+        Ok(v)
+    }
+
+    /// Unregisters a buffer and releases its OpenCL memory object.
+    /// The host memory, if any, is left to the caller.
+    fn take_buffer<T>(&mut self, buf: &Buffer<T>) -> Result<BufferEntry, MCLError> {
+        self.buffer_entry(buf)?;
+        self.check_unmapped(buf.id)?;
+        if self.buffers.values().any(|e| e.parent == Some(buf.id)) {
+            return Err(MCLError::BufferHasSubBuffers);
+        }
+        // the pending commands may still use the buffer
+        let err = unsafe { cl_sys::clFinish(self.queue) };
+        check_cl_error(err, "clFinish")?;
+        let entry = self.buffers.remove(&buf.id).unwrap();
+        entry.release_mem();
+        Ok(entry)
+    }
+
+    /// Unmaps back to the device a buffer mapped on the host.
+    fn unmap_buffer<T>(&mut self, buf: Buffer<T>) -> Result<(), MCLError> {
+        let queue = self.queue;
+        self.buffers
+            .get_mut(&buf.id)
+            .ok_or(MCLError::BufferNotRegistered)?
+            .unmap(queue)
+    }
+}
+
+/// Checks that `range` is a valid range of elements of a buffer.
+fn check_range(range: &std::ops::Range<usize>, buffer_len: usize) -> Result<(), MCLError> {
+    if range.start > range.end || range.end > buffer_len {
+        return Err(MCLError::OutOfRange {
+            offset: range.start,
+            len: range.end.saturating_sub(range.start),
+            buffer_len,
+        });
+    }
+    Ok(())
+}
+
+/// Host access to a mapped buffer, obtained with [Accel::map_buffer].
+/// Dereferences to a slice of the buffer elements.
+/// The buffer is unmapped when the guard is dropped.
+///
+/// The guard borrows the [Accel], so that it cannot outlive it:
+/// ```compile_fail
+/// # let mut cldev = minicl::Accel::new("".to_string(), 0).unwrap();
+/// let buf = cldev.register_buffer(vec![0i32; 16]).unwrap();
+/// let mapped = cldev.map_buffer(buf).unwrap();
+/// drop(cldev);
+/// println!("{}", mapped[0]);
+/// ```
+pub struct MappedBuffer<'a, T> {
+    accel: &'a mut Accel,
+    buffer: Buffer<T>,
+    ptr: *mut T,
+    mapped: bool,
+}
+
+impl<T> MappedBuffer<'_, T> {
+    /// Gives the buffer back to the device.
+    /// Same as dropping the guard, but the errors are returned.
+    pub fn unmap(mut self) -> Result<(), MCLError> {
+        self.mapped = false;
+        self.accel.unmap_buffer(self.buffer)
+    }
+
+    /// Handle on the mapped buffer.
+    pub fn buffer(&self) -> Buffer<T> {
+        self.buffer
+    }
+}
+
+impl<T> std::ops::Deref for MappedBuffer<'_, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.buffer.len) }
+    }
+}
+
+impl<T> std::ops::DerefMut for MappedBuffer<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.buffer.len) }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for MappedBuffer<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Read-only host access to a mapped buffer, obtained with [Accel::map_read].
+/// The buffer is unmapped when the guard is dropped.
+pub struct ReadMappedBuffer<'a, T>(MappedBuffer<'a, T>);
+
+impl<T> ReadMappedBuffer<'_, T> {
+    /// Gives the buffer back to the device.
+    /// Same as dropping the guard, but the errors are returned.
+    pub fn unmap(self) -> Result<(), MCLError> {
+        self.0.unmap()
+    }
+
+    /// Handle on the mapped buffer.
+    pub fn buffer(&self) -> Buffer<T> {
+        self.0.buffer
+    }
+}
+
+impl<T> std::ops::Deref for ReadMappedBuffer<'_, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for ReadMappedBuffer<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T> Drop for MappedBuffer<'_, T> {
+    fn drop(&mut self) {
+        if !self.mapped {
+            return;
+        }
+        if let Err(e) = self.accel.unmap_buffer(self.buffer) {
+            log::error!("Cannot unmap buffer {}: {}", self.buffer.id, e);
+        }
+    }
+}
+
+/// Conversion for buffer. We provide additional
+/// checks for better safety: the buffer must be registered
+/// in this [Accel] and not currently mapped to the host.
+impl<T> TrueArg for Buffer<T> {
+    fn true_arg(&self, dev: &Accel) -> Result<*const cl_sys::c_void, MCLError> {
+        let entry = dev.buffer_entry(self)?;
+        dev.check_unmapped(self.id)?;
+        Ok(&entry.mem as *const _ as *const cl_sys::c_void)
+    }
+    fn arg_size(&self) -> usize {
+        std::mem::size_of::<cl_sys::cl_mem>()
+    }
+}
+
+// some unit tests
+#[test]
+fn test_mem_flags() {
+    let flags = MemFlags::READ_ONLY | MemFlags::HOST_READ_ONLY;
+    assert!(flags.contains(MemFlags::READ_ONLY));
+    assert!(!flags.contains(MemFlags::WRITE_ONLY));
+    assert!(flags.host_readable());
+    assert!(!flags.host_writable());
+    assert!(MemFlags::READ_WRITE.host_readable() && MemFlags::READ_WRITE.host_writable());
+    assert!(!MemFlags::HOST_NO_ACCESS.host_readable());
+    assert!(!MemFlags::HOST_WRITE_ONLY.host_readable());
+}
@@ -0,0 +1,204 @@
+//! On-disk cache of the compiled OpenCL programs.
+//!
+//! The binaries are stored in files named after a hash of the source,
+//! the build options, and the name and driver version of the device.
+//! The headers included from the `-I` directories of the options are
+//! hashed too. Any change of these invalidates the cached binary.
+use crate::{check_cl_error, Device, MCLError, SourceLoader};
+use std::path::{Path, PathBuf};
+
+/// Extension of the cached binaries.
+const EXTENSION: &str = "clbin";
+
+/// Returns the cache file of a program.
+pub(crate) fn cache_file(
+    dir: &Path,
+    device: &Device,
+    oclsource: &str,
+    options: &str,
+) -> Result<PathBuf, MCLError> {
+    let mut hash = Fnv64::new();
+    hash.write(oclsource.as_bytes());
+    hash.write(options.as_bytes());
+    let loader = include_dirs(options)
+        .into_iter()
+        .fold(SourceLoader::new(), |loader, dir| loader.include_dir(dir));
+    for file in loader.included_files(oclsource) {
+        hash.write(file.display().to_string().as_bytes());
+        hash.write(&std::fs::read(&file).unwrap_or_default());
+    }
+    hash.write(device.name()?.as_bytes());
+    hash.write(device.version()?.as_bytes());
+    hash.write(device.driver_version()?.as_bytes());
+    Ok(dir.join(format!("{:016x}.{}", hash.finish(), EXTENSION)))
+}
+
+/// Directories of the `-I` options, which may be quoted
+/// (see [BuildOptions::include_dir](crate::BuildOptions::include_dir)).
+fn include_dirs(options: &str) -> Vec<PathBuf> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let (mut quoted, mut in_word) = (false, false);
+    for c in options.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    let mut dirs = vec![];
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        if word == "-I" {
+            dirs.extend(words.next().map(PathBuf::from));
+        } else if let Some(dir) = word.strip_prefix("-I") {
+            dirs.push(PathBuf::from(dir));
+        }
+    }
+    dirs
+}
+
+/// Reads a cached binary, if any.
+pub(crate) fn load(file: &Path) -> Option<Vec<u8>> {
+    std::fs::read(file).ok().filter(|binary| !binary.is_empty())
+}
+
+/// Saves the binary of a built program.
+/// Errors are only logged: the cache is an optimization.
+pub(crate) fn store(file: &Path, program: cl_sys::cl_program) {
+    let result = program_binary(program).and_then(|binary| {
+        let write = |binary: &[u8]| -> std::io::Result<()> {
+            if let Some(dir) = file.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            // write then rename, so that a concurrent run never reads a partial file
+            let tmp = file.with_extension(format!("{}.{}", EXTENSION, std::process::id()));
+            std::fs::write(&tmp, binary)?;
+            std::fs::rename(&tmp, file)
+        };
+        write(&binary).map_err(|error| MCLError::Io {
+            path: file.to_path_buf(),
+            error,
+        })
+    });
+    match result {
+        Ok(()) => log::debug!("Program binary stored in {}", file.display()),
+        Err(e) => log::warn!("Cannot store the program binary: {}", e),
+    }
+}
+
+/// Removes all the cached binaries of a directory.
+pub fn clear_cache<P: AsRef<Path>>(dir: P) -> Result<(), MCLError> {
+    let dir = dir.as_ref();
+    let io_error = |error| MCLError::Io {
+        path: dir.to_path_buf(),
+        error,
+    };
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(io_error(e)),
+    };
+    for entry in entries {
+        let path = entry.map_err(io_error)?.path();
+        if path.extension().is_some_and(|ext| ext == EXTENSION) {
+            std::fs::remove_file(&path).map_err(io_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Gets the binary of a program built for a single device.
+fn program_binary(program: cl_sys::cl_program) -> Result<Vec<u8>, MCLError> {
+    let mut size: usize = 0;
+    let err = unsafe {
+        cl_sys::clGetProgramInfo(
+            program,
+            cl_sys::CL_PROGRAM_BINARY_SIZES,
+            std::mem::size_of::<usize>(),
+            &mut size as *mut usize as *mut cl_sys::c_void,
+            std::ptr::null_mut(),
+        )
+    };
+    check_cl_error(err, "clGetProgramInfo")?;
+    let mut binary = vec![0u8; size];
+    let mut ptr = binary.as_mut_ptr();
+    let err = unsafe {
+        cl_sys::clGetProgramInfo(
+            program,
+            cl_sys::CL_PROGRAM_BINARIES,
+            std::mem::size_of::<*mut u8>(),
+            &mut ptr as *mut *mut u8 as *mut cl_sys::c_void,
+            std::ptr::null_mut(),
+        )
+    };
+    check_cl_error(err, "clGetProgramInfo")?;
+    Ok(binary)
+}
+
+/// FNV-1a hash. Unlike the std hashers, its value is stable
+/// across Rust versions, which is needed for a persistent cache.
+struct Fnv64(u64);
+
+impl Fnv64 {
+    fn new() -> Self {
+        Fnv64(0xcbf2_9ce4_8422_2325)
+    }
+
+    /// Hashes the bytes, prefixed by their length.
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// some unit tests
+#[test]
+fn test_fnv() {
+    let mut a = Fnv64::new();
+    a.write(b"ab");
+    a.write(b"c");
+    let mut b = Fnv64::new();
+    b.write(b"a");
+    b.write(b"bc");
+    assert_ne!(a.finish(), b.finish());
+}
+
+#[test]
+fn test_include_dirs() {
+    let options = crate::BuildOptions::new()
+        .define("N", 4)
+        .include_dir("my kernels")
+        .include_dir("inc")
+        .raw("-Iother")
+        .to_string();
+    assert_eq!(
+        include_dirs(&options),
+        vec![
+            PathBuf::from("my kernels"),
+            PathBuf::from("inc"),
+            PathBuf::from("other")
+        ]
+    );
+    assert!(include_dirs("-D N=4 -cl-fast-relaxed-math").is_empty());
+}
@@ -0,0 +1,423 @@
+//! Enumeration and introspection of the OpenCL platforms and devices.
+//!
+//! [Platform] and [Device] are lightweight handles on the OpenCL ids.
+//! Their properties are queried from the driver on demand, or all at
+//! once with [Platform::info] and [Device::info].
+//! # Examples
+//! ```no_run
+//! for platform in minicl::platforms()? {
+//!     println!("{:?}", platform.info()?);
+//!     for device in platform.devices()? {
+//!         println!("{:#?}", device.info()?);
+//!     }
+//! }
+//! # Ok::<(), minicl::MCLError>(())
+//! ```
+use crate::{check_cl_error, MCLError};
+
+/// Returns all the OpenCL platforms available on this computer.
+pub fn platforms() -> Result<Vec<Platform>, MCLError> {
+    Ok(platform_ids()?
+        .into_iter()
+        .enumerate()
+        .map(|(index, id)| Platform { id, index })
+        .collect())
+}
+
+/// Handle on an OpenCL platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Platform {
+    pub(crate) id: cl_sys::cl_platform_id,
+    index: usize,
+}
+
+/// Properties of an OpenCL platform.
+#[derive(Debug, Clone)]
+pub struct PlatformInfo {
+    pub index: usize,
+    pub name: String,
+    pub vendor: String,
+    pub version: String,
+    pub profile: String,
+    pub extensions: Vec<String>,
+}
+
+impl Platform {
+    /// Index of the platform, as expected by [Accel::new](crate::Accel::new).
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Name of the platform.
+    pub fn name(&self) -> Result<String, MCLError> {
+        platform_info_string(self.id, cl_sys::CL_PLATFORM_NAME)
+    }
+
+    /// Vendor of the platform.
+    pub fn vendor(&self) -> Result<String, MCLError> {
+        platform_info_string(self.id, cl_sys::CL_PLATFORM_VENDOR)
+    }
+
+    /// All the devices of the platform, whatever their type.
+    pub fn devices(&self) -> Result<Vec<Device>, MCLError> {
+        device_ids(self.id, DeviceType::ALL)
+    }
+
+    /// Queries all the properties of the platform.
+    pub fn info(&self) -> Result<PlatformInfo, MCLError> {
+        Ok(PlatformInfo {
+            index: self.index,
+            name: self.name()?,
+            vendor: self.vendor()?,
+            version: platform_info_string(self.id, cl_sys::CL_PLATFORM_VERSION)?,
+            profile: platform_info_string(self.id, cl_sys::CL_PLATFORM_PROFILE)?,
+            extensions: split_extensions(&platform_info_string(
+                self.id,
+                cl_sys::CL_PLATFORM_EXTENSIONS,
+            )?),
+        })
+    }
+}
+
+/// OpenCL device type. Types can be combined with `|`
+/// for selecting several kinds of devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceType(pub cl_sys::cl_device_type);
+
+impl DeviceType {
+    pub const DEFAULT: DeviceType = DeviceType(cl_sys::CL_DEVICE_TYPE_DEFAULT);
+    pub const CPU: DeviceType = DeviceType(cl_sys::CL_DEVICE_TYPE_CPU);
+    pub const GPU: DeviceType = DeviceType(cl_sys::CL_DEVICE_TYPE_GPU);
+    pub const ACCELERATOR: DeviceType = DeviceType(cl_sys::CL_DEVICE_TYPE_ACCELERATOR);
+    pub const CUSTOM: DeviceType = DeviceType(cl_sys::CL_DEVICE_TYPE_CUSTOM);
+    pub const ALL: DeviceType = DeviceType(cl_sys::CL_DEVICE_TYPE_ALL);
+
+    /// Returns true if at least one of the types of `other` is in `self`.
+    pub fn intersects(self, other: DeviceType) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl std::ops::BitOr for DeviceType {
+    type Output = DeviceType;
+    fn bitor(self, rhs: DeviceType) -> DeviceType {
+        DeviceType(self.0 | rhs.0)
+    }
+}
+
+/// Handle on an OpenCL device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Device {
+    pub(crate) id: cl_sys::cl_device_id,
+    pub(crate) platform: cl_sys::cl_platform_id,
+}
+
+/// Properties of an OpenCL device.
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub vendor: String,
+    pub version: String,
+    pub driver_version: String,
+    pub opencl_c_version: String,
+    pub device_type: DeviceType,
+    pub available: bool,
+    pub compute_units: u32,
+    pub max_clock_frequency: u32,
+    pub max_work_group_size: usize,
+    pub max_work_item_sizes: Vec<usize>,
+    pub global_mem_size: u64,
+    pub local_mem_size: u64,
+    pub max_mem_alloc_size: u64,
+    /// Alignment of the buffers, in bits.
+    pub mem_base_addr_align: u32,
+    pub extensions: Vec<String>,
+    /// Double precision support.
+    pub fp64: bool,
+}
+
+impl Device {
+    /// Name of the device.
+    pub fn name(&self) -> Result<String, MCLError> {
+        device_info_string(self.id, cl_sys::CL_DEVICE_NAME)
+    }
+
+    /// Vendor of the device.
+    pub fn vendor(&self) -> Result<String, MCLError> {
+        device_info_string(self.id, cl_sys::CL_DEVICE_VENDOR)
+    }
+
+    /// Type of the device (CPU, GPU, etc.).
+    pub fn device_type(&self) -> Result<DeviceType, MCLError> {
+        let t: cl_sys::cl_device_type = device_info(self.id, cl_sys::CL_DEVICE_TYPE)?;
+        Ok(DeviceType(t))
+    }
+
+    /// Platform of the device.
+    pub fn platform(&self) -> Result<Platform, MCLError> {
+        let index = platform_ids()?
+            .iter()
+            .position(|&id| id == self.platform)
+            .ok_or_else(|| {
+                MCLError::PlatformNotFound("Platform of the device not found".to_string())
+            })?;
+        Ok(Platform {
+            id: self.platform,
+            index,
+        })
+    }
+
+    /// OpenCL version supported by the device.
+    pub fn version(&self) -> Result<String, MCLError> {
+        device_info_string(self.id, cl_sys::CL_DEVICE_VERSION)
+    }
+
+    /// Version of the OpenCL driver.
+    pub fn driver_version(&self) -> Result<String, MCLError> {
+        device_info_string(self.id, cl_sys::CL_DRIVER_VERSION)
+    }
+
+    /// Number of parallel compute units.
+    pub fn compute_units(&self) -> Result<u32, MCLError> {
+        device_info::<cl_sys::cl_uint>(self.id, cl_sys::CL_DEVICE_MAX_COMPUTE_UNITS)
+    }
+
+    /// Maximal number of work-items in a work-group.
+    pub fn max_work_group_size(&self) -> Result<usize, MCLError> {
+        device_info::<usize>(self.id, cl_sys::CL_DEVICE_MAX_WORK_GROUP_SIZE)
+    }
+
+    /// Maximal number of work-items in each dimension of a work-group.
+    pub fn max_work_item_sizes(&self) -> Result<Vec<usize>, MCLError> {
+        let dims: cl_sys::cl_uint =
+            device_info(self.id, cl_sys::CL_DEVICE_MAX_WORK_ITEM_DIMENSIONS)?;
+        let mut sizes = vec![0usize; dims as usize];
+        let err = unsafe {
+            cl_sys::clGetDeviceInfo(
+                self.id,
+                cl_sys::CL_DEVICE_MAX_WORK_ITEM_SIZES,
+                sizes.len() * std::mem::size_of::<usize>(),
+                sizes.as_mut_ptr() as *mut cl_sys::c_void,
+                std::ptr::null_mut(),
+            )
+        };
+        check_cl_error(err, "clGetDeviceInfo")?;
+        Ok(sizes)
+    }
+
+    /// Size of the global memory, in bytes.
+    pub fn global_mem_size(&self) -> Result<u64, MCLError> {
+        device_info::<cl_sys::cl_ulong>(self.id, cl_sys::CL_DEVICE_GLOBAL_MEM_SIZE)
+    }
+
+    /// Size of the local memory, in bytes.
+    pub fn local_mem_size(&self) -> Result<u64, MCLError> {
+        device_info::<cl_sys::cl_ulong>(self.id, cl_sys::CL_DEVICE_LOCAL_MEM_SIZE)
+    }
+
+    /// Maximal size of a single buffer, in bytes.
+    pub fn max_mem_alloc_size(&self) -> Result<u64, MCLError> {
+        device_info::<cl_sys::cl_ulong>(self.id, cl_sys::CL_DEVICE_MAX_MEM_ALLOC_SIZE)
+    }
+
+    /// Alignment of the buffers, in bits.
+    pub fn mem_base_addr_align(&self) -> Result<u32, MCLError> {
+        device_info::<cl_sys::cl_uint>(self.id, cl_sys::CL_DEVICE_MEM_BASE_ADDR_ALIGN)
+    }
+
+    /// Extensions supported by the device.
+    pub fn extensions(&self) -> Result<Vec<String>, MCLError> {
+        Ok(split_extensions(&device_info_string(
+            self.id,
+            cl_sys::CL_DEVICE_EXTENSIONS,
+        )?))
+    }
+
+    /// Returns true if the device supports double precision.
+    /// `CL_DEVICE_DOUBLE_FP_CONFIG` is only queried without the
+    /// `cl_khr_fp64` extension, since OpenCL 1.0 and 1.1 devices may
+    /// reject it (`CL_INVALID_VALUE`), which means no support.
+    pub fn has_fp64(&self) -> Result<bool, MCLError> {
+        if self.extensions()?.iter().any(|e| e == "cl_khr_fp64") {
+            return Ok(true);
+        }
+        let config = device_info::<cl_sys::cl_device_fp_config>(
+            self.id,
+            cl_sys::CL_DEVICE_DOUBLE_FP_CONFIG,
+        );
+        match config {
+            Ok(config) => Ok(config != 0),
+            Err(e) if e.code() == Some(cl_sys::CL_INVALID_VALUE) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Queries all the properties of the device.
+    pub fn info(&self) -> Result<DeviceInfo, MCLError> {
+        let available: cl_sys::cl_bool = device_info(self.id, cl_sys::CL_DEVICE_AVAILABLE)?;
+        Ok(DeviceInfo {
+            name: self.name()?,
+            vendor: self.vendor()?,
+            version: self.version()?,
+            driver_version: self.driver_version()?,
+            opencl_c_version: device_info_string(self.id, cl_sys::CL_DEVICE_OPENCL_C_VERSION)?,
+            device_type: self.device_type()?,
+            available: available != cl_sys::CL_FALSE,
+            compute_units: self.compute_units()?,
+            max_clock_frequency: device_info(self.id, cl_sys::CL_DEVICE_MAX_CLOCK_FREQUENCY)?,
+            max_work_group_size: self.max_work_group_size()?,
+            max_work_item_sizes: self.max_work_item_sizes()?,
+            global_mem_size: self.global_mem_size()?,
+            local_mem_size: self.local_mem_size()?,
+            max_mem_alloc_size: self.max_mem_alloc_size()?,
+            mem_base_addr_align: self.mem_base_addr_align()?,
+            extensions: self.extensions()?,
+            fp64: self.has_fp64()?,
+        })
+    }
+}
+
+/// Returns the ids of all the OpenCL platforms.
+pub(crate) fn platform_ids() -> Result<Vec<cl_sys::cl_platform_id>, MCLError> {
+    let mut nb_platforms: u32 = 0;
+    let err = unsafe { cl_sys::clGetPlatformIDs(0, std::ptr::null_mut(), &mut nb_platforms) };
+    check_cl_error(err, "clGetPlatformIDs")?;
+    if nb_platforms == 0 {
+        return Ok(vec![]);
+    }
+    let mut platforms = vec![std::ptr::null_mut(); nb_platforms as usize];
+    let err = unsafe {
+        cl_sys::clGetPlatformIDs(nb_platforms, platforms.as_mut_ptr(), &mut nb_platforms)
+    };
+    check_cl_error(err, "clGetPlatformIDs")?;
+    Ok(platforms)
+}
+
+/// Returns the devices of a given type on a platform.
+/// An empty list is returned if there is no such device.
+pub(crate) fn device_ids(
+    platform: cl_sys::cl_platform_id,
+    device_type: DeviceType,
+) -> Result<Vec<Device>, MCLError> {
+    let mut nb_devices: u32 = 0;
+    let err = unsafe {
+        cl_sys::clGetDeviceIDs(platform, device_type.0, 0, std::ptr::null_mut(), &mut nb_devices)
+    };
+    if err == cl_sys::CL_DEVICE_NOT_FOUND {
+        return Ok(vec![]);
+    }
+    check_cl_error(err, "clGetDeviceIDs")?;
+    if nb_devices == 0 {
+        return Ok(vec![]);
+    }
+    let mut ids = vec![std::ptr::null_mut(); nb_devices as usize];
+    let err = unsafe {
+        cl_sys::clGetDeviceIDs(
+            platform,
+            device_type.0,
+            nb_devices,
+            ids.as_mut_ptr(),
+            &mut nb_devices,
+        )
+    };
+    check_cl_error(err, "clGetDeviceIDs")?;
+    Ok(ids.into_iter().map(|id| Device { id, platform }).collect())
+}
+
+/// Queries a string property of a platform.
+pub(crate) fn platform_info_string(
+    platform: cl_sys::cl_platform_id,
+    param: cl_sys::cl_platform_info,
+) -> Result<String, MCLError> {
+    let mut size: usize = 0;
+    let err = unsafe {
+        cl_sys::clGetPlatformInfo(platform, param, 0, std::ptr::null_mut(), &mut size)
+    };
+    check_cl_error(err, "clGetPlatformInfo")?;
+    let mut bytes = vec![0u8; size];
+    let err = unsafe {
+        cl_sys::clGetPlatformInfo(
+            platform,
+            param,
+            size,
+            bytes.as_mut_ptr() as *mut cl_sys::c_void,
+            std::ptr::null_mut(),
+        )
+    };
+    check_cl_error(err, "clGetPlatformInfo")?;
+    Ok(c_bytes_to_string(bytes))
+}
+
+/// Queries a string property of a device.
+pub(crate) fn device_info_string(
+    device: cl_sys::cl_device_id,
+    param: cl_sys::cl_device_info,
+) -> Result<String, MCLError> {
+    let mut size: usize = 0;
+    let err =
+        unsafe { cl_sys::clGetDeviceInfo(device, param, 0, std::ptr::null_mut(), &mut size) };
+    check_cl_error(err, "clGetDeviceInfo")?;
+    let mut bytes = vec![0u8; size];
+    let err = unsafe {
+        cl_sys::clGetDeviceInfo(
+            device,
+            param,
+            size,
+            bytes.as_mut_ptr() as *mut cl_sys::c_void,
+            std::ptr::null_mut(),
+        )
+    };
+    check_cl_error(err, "clGetDeviceInfo")?;
+    Ok(c_bytes_to_string(bytes))
+}
+
+/// Queries a scalar property of a device.
+pub(crate) fn device_info<T: Copy + Default>(
+    device: cl_sys::cl_device_id,
+    param: cl_sys::cl_device_info,
+) -> Result<T, MCLError> {
+    let mut value = T::default();
+    let err = unsafe {
+        cl_sys::clGetDeviceInfo(
+            device,
+            param,
+            std::mem::size_of::<T>(),
+            &mut value as *mut T as *mut cl_sys::c_void,
+            std::ptr::null_mut(),
+        )
+    };
+    check_cl_error(err, "clGetDeviceInfo")?;
+    Ok(value)
+}
+
+/// Splits a space separated list of extensions.
+fn split_extensions(extensions: &str) -> Vec<String> {
+    extensions.split_whitespace().map(|e| e.to_string()).collect()
+}
+
+/// Converts a nul-terminated C string returned by OpenCL.
+fn c_bytes_to_string(mut bytes: Vec<u8>) -> String {
+    if let Some(end) = bytes.iter().position(|&b| b == 0) {
+        bytes.truncate(end);
+    }
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+// some unit tests
+#[test]
+fn test_platforms() -> Result<(), MCLError> {
+    let platforms = platforms()?;
+    assert!(!platforms.is_empty());
+    for (index, platform) in platforms.iter().enumerate() {
+        let info = platform.info()?;
+        assert_eq!(info.index, index);
+        for device in platform.devices()? {
+            let info = device.info()?;
+            assert_eq!(device.platform()?, *platform);
+            assert!(!info.name.is_empty());
+            assert!(info.max_work_group_size > 0);
+            assert!(info.max_work_item_sizes.len() >= 3);
+        }
+    }
+    Ok(())
+}
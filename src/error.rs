@@ -0,0 +1,331 @@
+//! Error type of minicl.
+use crate::BuildDiagnostic;
+use std::fmt;
+
+/// Errors returned by minicl.
+#[derive(Debug)]
+pub enum MCLError {
+    /// An OpenCL function returned an error code.
+    /// `context` gives additional information, such as the kernel
+    /// name or the argument index (it may be empty).
+    Cl {
+        code: cl_sys::cl_int,
+        func: &'static str,
+        context: String,
+    },
+    /// The compilation of the OpenCL program failed.
+    /// `log` is the full compiler output for `device`, and `diagnostics`
+    /// the errors and warnings parsed from it.
+    BuildFailed {
+        device: String,
+        options: String,
+        log: String,
+        diagnostics: Vec<BuildDiagnostic>,
+    },
+    /// No OpenCL platform found, or platform index out of range.
+    PlatformNotFound(String),
+    /// No device matches the selection criteria.
+    DeviceNotFound(String),
+    /// No program with this name.
+    ProgramNotFound(String),
+    /// A program with this name already exists.
+    ProgramAlreadyRegistered(String),
+    /// The kernel has not been registered.
+    KernelNotFound(String),
+    /// The kernel is already registered.
+    KernelAlreadyRegistered(String),
+    /// The buffer is not (or no longer) registered.
+    BufferNotRegistered,
+    /// The buffer belongs to another [Accel](crate::Accel).
+    ForeignBuffer,
+    /// The buffer is mapped on the host and cannot be used by the device.
+    BufferMapped,
+    /// The buffer is not mapped on the host.
+    BufferNotMapped,
+    /// The buffer cannot be released while it has sub-buffers.
+    BufferHasSubBuffers,
+    /// The access flags of the buffer forbid the requested access.
+    BufferAccess(String),
+    /// The range `offset..offset + len` (in elements) exceeds the buffer length.
+    OutOfRange {
+        offset: usize,
+        len: usize,
+        buffer_len: usize,
+    },
+    /// Two sizes (in elements or bytes) that should match do not.
+    SizeMismatch { expected: usize, found: usize },
+    /// A placeholder of a [KernelSource](crate::KernelSource) has no value.
+    UnresolvedPlaceholder { name: String, line: usize },
+    /// An OpenCL source file cannot be read.
+    Io {
+        path: std::path::PathBuf,
+        error: std::io::Error,
+    },
+    /// Error in an OpenCL source file, at the given line.
+    Source {
+        file: std::path::PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// The global work size is not compatible with the local work size.
+    InvalidWorkSize {
+        global: crate::NDRange,
+        local: crate::NDRange,
+    },
+    /// The offset of a range does not have the dimension of its sizes.
+    OffsetDimension { dim: usize, offset_dim: usize },
+    /// The local work size exceeds the limits of the device.
+    WorkGroupTooLarge {
+        local: crate::NDRange,
+        max_work_item_sizes: Vec<usize>,
+        max_work_group_size: usize,
+    },
+    /// The arguments receiving the true global size of a padded kernel
+    /// (`dim` of them from index `first`) exceed its `num_args` arguments.
+    PaddedSizeArgs {
+        kernel: String,
+        first: usize,
+        dim: usize,
+        num_args: usize,
+    },
+    /// The true global size of a padded kernel does not fit in the
+    /// `int` arguments receiving it.
+    GlobalSizeOverflow {
+        kernel: String,
+        global: crate::NDRange,
+    },
+    /// The status code given to [UserEvent::set_failed](crate::UserEvent::set_failed)
+    /// is not a negative error code (`0` would complete the event).
+    UserEventStatus(cl_sys::cl_int),
+    StdCString(std::ffi::NulError),
+    Other(String),
+}
+
+impl MCLError {
+    /// Returns the OpenCL error code, if the error comes from an OpenCL call.
+    pub fn code(&self) -> Option<cl_sys::cl_int> {
+        match self {
+            MCLError::Cl { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Adds context to an OpenCL error. Other errors are unchanged.
+    pub(crate) fn with_context(self, ctx: String) -> Self {
+        match self {
+            MCLError::Cl { code, func, .. } => MCLError::Cl {
+                code,
+                func,
+                context: ctx,
+            },
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for MCLError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MCLError::Cl {
+                code,
+                func,
+                context,
+            } => {
+                write!(f, "{} failed with {} ({})", func, error_text(*code), code)?;
+                if !context.is_empty() {
+                    write!(f, " [{}]", context)?;
+                }
+                Ok(())
+            }
+            MCLError::BuildFailed {
+                device,
+                options,
+                log,
+                ..
+            } => write!(
+                f,
+                "OpenCL build failed on {} (options \"{}\"):\n{}",
+                device, options, log
+            ),
+            MCLError::PlatformNotFound(msg) => write!(f, "Platform not found: {}", msg),
+            MCLError::DeviceNotFound(msg) => write!(f, "Device not found: {}", msg),
+            MCLError::ProgramNotFound(name) => write!(f, "Program '{}' not found", name),
+            MCLError::ProgramAlreadyRegistered(name) => {
+                write!(f, "Program '{}' already registered", name)
+            }
+            MCLError::KernelNotFound(name) => write!(f, "Kernel '{}' not found", name),
+            MCLError::KernelAlreadyRegistered(name) => {
+                write!(f, "Kernel '{}' already registered", name)
+            }
+            MCLError::BufferNotRegistered => write!(f, "Buffer not registered"),
+            MCLError::ForeignBuffer => write!(f, "Buffer registered in another Accel"),
+            MCLError::BufferMapped => write!(f, "Buffer is mapped on the host"),
+            MCLError::BufferNotMapped => write!(f, "Buffer is not mapped on the host"),
+            MCLError::BufferHasSubBuffers => write!(f, "Buffer has sub-buffers"),
+            MCLError::BufferAccess(msg) => write!(f, "Buffer access not allowed: {}", msg),
+            MCLError::OutOfRange {
+                offset,
+                len,
+                buffer_len,
+            } => write!(
+                f,
+                "Range {}..{} out of the buffer of length {}",
+                offset,
+                offset + len,
+                buffer_len
+            ),
+            MCLError::SizeMismatch { expected, found } => {
+                write!(f, "Size mismatch: expected {}, found {}", expected, found)
+            }
+            MCLError::UnresolvedPlaceholder { name, line } => {
+                write!(f, "No value for placeholder '{}' (line {})", name, line)
+            }
+            MCLError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            MCLError::Source {
+                file,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", file.display(), line, message),
+            MCLError::InvalidWorkSize { global, local } => write!(
+                f,
+                "Global size {} must be a multiple of local size {}",
+                global, local
+            ),
+            MCLError::OffsetDimension { dim, offset_dim } => write!(
+                f,
+                "Offset of dimension {} for a range of dimension {}",
+                offset_dim, dim
+            ),
+            MCLError::WorkGroupTooLarge {
+                local,
+                max_work_item_sizes,
+                max_work_group_size,
+            } => write!(
+                f,
+                "Local size {} exceeds the device limits \
+                 (work-item sizes {:?}, work-group size {})",
+                local, max_work_item_sizes, max_work_group_size
+            ),
+            MCLError::PaddedSizeArgs {
+                kernel,
+                first,
+                dim,
+                num_args,
+            } => write!(
+                f,
+                "Kernel '{}' has {} arguments, cannot receive the {} global sizes from argument {}",
+                kernel, num_args, dim, first
+            ),
+            MCLError::GlobalSizeOverflow { kernel, global } => write!(
+                f,
+                "Global size {} of kernel '{}' does not fit in an int",
+                global, kernel
+            ),
+            MCLError::UserEventStatus(code) => {
+                write!(f, "Status {} of a failed user event is not negative", code)
+            }
+            MCLError::StdCString(err) => write!(f, "{}", err),
+            MCLError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for MCLError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MCLError::StdCString(err) => Some(err),
+            MCLError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::ffi::NulError> for MCLError {
+    fn from(err: std::ffi::NulError) -> Self {
+        MCLError::StdCString(err)
+    }
+}
+
+// Helper to convert CL error codes to Result
+pub(crate) fn check_cl_error(err: cl_sys::cl_int, func: &'static str) -> Result<(), MCLError> {
+    if err == cl_sys::CL_SUCCESS {
+        Ok(())
+    } else {
+        Err(MCLError::Cl {
+            code: err,
+            func,
+            context: String::new(),
+        })
+    }
+}
+
+pub fn error_text(error_code: cl_sys::cl_int) -> &'static str {
+    match error_code {
+        cl_sys::CL_SUCCESS => "CL_SUCCESS",
+        cl_sys::CL_DEVICE_NOT_FOUND => "CL_DEVICE_NOT_FOUND",
+        cl_sys::CL_DEVICE_NOT_AVAILABLE => "CL_DEVICE_NOT_AVAILABLE",
+        cl_sys::CL_COMPILER_NOT_AVAILABLE => "CL_COMPILER_NOT_AVAILABLE",
+        cl_sys::CL_MEM_OBJECT_ALLOCATION_FAILURE => "CL_MEM_OBJECT_ALLOCATION_FAILURE",
+        cl_sys::CL_OUT_OF_RESOURCES => "CL_OUT_OF_RESOURCES",
+        cl_sys::CL_OUT_OF_HOST_MEMORY => "CL_OUT_OF_HOST_MEMORY",
+        cl_sys::CL_PROFILING_INFO_NOT_AVAILABLE => "CL_PROFILING_INFO_NOT_AVAILABLE",
+        cl_sys::CL_MEM_COPY_OVERLAP => "CL_MEM_COPY_OVERLAP",
+        cl_sys::CL_IMAGE_FORMAT_MISMATCH => "CL_IMAGE_FORMAT_MISMATCH",
+        cl_sys::CL_IMAGE_FORMAT_NOT_SUPPORTED => "CL_IMAGE_FORMAT_NOT_SUPPORTED",
+        cl_sys::CL_BUILD_PROGRAM_FAILURE => "CL_BUILD_PROGRAM_FAILURE",
+        cl_sys::CL_MAP_FAILURE => "CL_MAP_FAILURE",
+        cl_sys::CL_MISALIGNED_SUB_BUFFER_OFFSET => "CL_MISALIGNED_SUB_BUFFER_OFFSET",
+        cl_sys::CL_EXEC_STATUS_ERROR_FOR_EVENTS_IN_WAIT_LIST => {
+            "CL_EXEC_STATUS_ERROR_FOR_EVENTS_IN_WAIT_LIST"
+        }
+        cl_sys::CL_COMPILE_PROGRAM_FAILURE => "CL_COMPILE_PROGRAM_FAILURE",
+        cl_sys::CL_LINKER_NOT_AVAILABLE => "CL_LINKER_NOT_AVAILABLE",
+        cl_sys::CL_LINK_PROGRAM_FAILURE => "CL_LINK_PROGRAM_FAILURE",
+        cl_sys::CL_DEVICE_PARTITION_FAILED => "CL_DEVICE_PARTITION_FAILED",
+        cl_sys::CL_KERNEL_ARG_INFO_NOT_AVAILABLE => "CL_KERNEL_ARG_INFO_NOT_AVAILABLE",
+        cl_sys::CL_INVALID_VALUE => "CL_INVALID_VALUE",
+        cl_sys::CL_INVALID_DEVICE_TYPE => "CL_INVALID_DEVICE_TYPE",
+        cl_sys::CL_INVALID_PLATFORM => "CL_INVALID_PLATFORM",
+        cl_sys::CL_INVALID_DEVICE => "CL_INVALID_DEVICE",
+        cl_sys::CL_INVALID_CONTEXT => "CL_INVALID_CONTEXT",
+        cl_sys::CL_INVALID_QUEUE_PROPERTIES => "CL_INVALID_QUEUE_PROPERTIES",
+        cl_sys::CL_INVALID_COMMAND_QUEUE => "CL_INVALID_COMMAND_QUEUE",
+        cl_sys::CL_INVALID_HOST_PTR => "CL_INVALID_HOST_PTR",
+        cl_sys::CL_INVALID_MEM_OBJECT => "CL_INVALID_MEM_OBJECT",
+        cl_sys::CL_INVALID_IMAGE_FORMAT_DESCRIPTOR => "CL_INVALID_IMAGE_FORMAT_DESCRIPTOR",
+        cl_sys::CL_INVALID_IMAGE_SIZE => "CL_INVALID_IMAGE_SIZE",
+        cl_sys::CL_INVALID_SAMPLER => "CL_INVALID_SAMPLER",
+        cl_sys::CL_INVALID_BINARY => "CL_INVALID_BINARY",
+        cl_sys::CL_INVALID_BUILD_OPTIONS => "CL_INVALID_BUILD_OPTIONS",
+        cl_sys::CL_INVALID_PROGRAM => "CL_INVALID_PROGRAM",
+        cl_sys::CL_INVALID_PROGRAM_EXECUTABLE => "CL_INVALID_PROGRAM_EXECUTABLE",
+        cl_sys::CL_INVALID_KERNEL_NAME => "CL_INVALID_KERNEL_NAME",
+        cl_sys::CL_INVALID_KERNEL_DEFINITION => "CL_INVALID_KERNEL_DEFINITION",
+        cl_sys::CL_INVALID_KERNEL => "CL_INVALID_KERNEL",
+        cl_sys::CL_INVALID_ARG_INDEX => "CL_INVALID_ARG_INDEX",
+        cl_sys::CL_INVALID_ARG_VALUE => "CL_INVALID_ARG_VALUE",
+        cl_sys::CL_INVALID_ARG_SIZE => "CL_INVALID_ARG_SIZE",
+        cl_sys::CL_INVALID_KERNEL_ARGS => "CL_INVALID_KERNEL_ARGS",
+        cl_sys::CL_INVALID_WORK_DIMENSION => "CL_INVALID_WORK_DIMENSION",
+        cl_sys::CL_INVALID_WORK_GROUP_SIZE => "CL_INVALID_WORK_GROUP_SIZE",
+        cl_sys::CL_INVALID_WORK_ITEM_SIZE => "CL_INVALID_WORK_ITEM_SIZE",
+        cl_sys::CL_INVALID_GLOBAL_OFFSET => "CL_INVALID_GLOBAL_OFFSET",
+        cl_sys::CL_INVALID_EVENT_WAIT_LIST => "CL_INVALID_EVENT_WAIT_LIST",
+        cl_sys::CL_INVALID_EVENT => "CL_INVALID_EVENT",
+        cl_sys::CL_INVALID_OPERATION => "CL_INVALID_OPERATION",
+        cl_sys::CL_INVALID_GL_OBJECT => "CL_INVALID_GL_OBJECT",
+        cl_sys::CL_INVALID_BUFFER_SIZE => "CL_INVALID_BUFFER_SIZE",
+        cl_sys::CL_INVALID_MIP_LEVEL => "CL_INVALID_MIP_LEVEL",
+        cl_sys::CL_INVALID_GLOBAL_WORK_SIZE => "CL_INVALID_GLOBAL_WORK_SIZE",
+        cl_sys::CL_INVALID_PROPERTY => "CL_INVALID_PROPERTY",
+        cl_sys::CL_INVALID_IMAGE_DESCRIPTOR => "CL_INVALID_IMAGE_DESCRIPTOR",
+        cl_sys::CL_INVALID_COMPILER_OPTIONS => "CL_INVALID_COMPILER_OPTIONS",
+        cl_sys::CL_INVALID_LINKER_OPTIONS => "CL_INVALID_LINKER_OPTIONS",
+        cl_sys::CL_INVALID_DEVICE_PARTITION_COUNT => "CL_INVALID_DEVICE_PARTITION_COUNT",
+        cl_sys::CL_INVALID_PIPE_SIZE => "CL_INVALID_PIPE_SIZE",
+        cl_sys::CL_INVALID_DEVICE_QUEUE => "CL_INVALID_DEVICE_QUEUE",
+        cl_sys::CL_INVALID_GL_SHAREGROUP_REFERENCE_KHR => "CL_INVALID_GL_SHAREGROUP_REFERENCE_KHR",
+        cl_sys::CL_PLATFORM_NOT_FOUND_KHR => "CL_PLATFORM_NOT_FOUND_KHR",
+        _ => "UNKNOWN_ERROR",
+    }
+}
@@ -0,0 +1,295 @@
+//! Events of the commands enqueued on the device.
+use crate::{check_cl_error, Accel, MCLError};
+
+/// Handle on a command enqueued on the device, such as a kernel run
+/// or a buffer copy.
+/// The command runs asynchronously: [wait](Event::wait) blocks until
+/// it is complete. Dropping the event does not cancel the command.
+/// Events can be given as wait lists to the next commands, e.g. with
+/// [run_kernel_after](Accel::run_kernel_after).
+#[derive(Debug)]
+pub struct Event {
+    pub(crate) event: cl_sys::cl_event,
+}
+
+// OpenCL event handles can be used from any thread, e.g. for completing
+// a user event while another thread waits for the gated commands.
+unsafe impl Send for Event {}
+unsafe impl Sync for Event {}
+
+/// Execution status of the command of an [Event].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventStatus {
+    Queued,
+    Submitted,
+    Running,
+    Complete,
+    /// The command has been aborted, with this (negative) error code.
+    Failed(cl_sys::cl_int),
+}
+
+impl EventStatus {
+    /// Returns true if the command is neither complete nor failed.
+    pub(crate) fn is_pending(self) -> bool {
+        matches!(
+            self,
+            EventStatus::Queued | EventStatus::Submitted | EventStatus::Running
+        )
+    }
+
+    fn from_code(code: cl_sys::cl_int) -> Self {
+        match code {
+            cl_sys::CL_QUEUED => EventStatus::Queued,
+            cl_sys::CL_SUBMITTED => EventStatus::Submitted,
+            cl_sys::CL_RUNNING => EventStatus::Running,
+            cl_sys::CL_COMPLETE => EventStatus::Complete,
+            code => EventStatus::Failed(code),
+        }
+    }
+}
+
+impl Event {
+    /// Blocks until the command is complete.
+    pub fn wait(&self) -> Result<(), MCLError> {
+        let err = unsafe { cl_sys::clWaitForEvents(1, &self.event) };
+        check_cl_error(err, "clWaitForEvents")
+    }
+
+    /// Current status of the command, without waiting.
+    pub fn status(&self) -> Result<EventStatus, MCLError> {
+        let mut status: cl_sys::cl_int = 0;
+        let err = unsafe {
+            cl_sys::clGetEventInfo(
+                self.event,
+                cl_sys::CL_EVENT_COMMAND_EXECUTION_STATUS,
+                std::mem::size_of::<cl_sys::cl_int>(),
+                &mut status as *mut cl_sys::cl_int as *mut cl_sys::c_void,
+                std::ptr::null_mut(),
+            )
+        };
+        check_cl_error(err, "clGetEventInfo")?;
+        Ok(EventStatus::from_code(status))
+    }
+
+    /// Returns true if the command is complete.
+    pub fn is_complete(&self) -> Result<bool, MCLError> {
+        Ok(self.status()? == EventStatus::Complete)
+    }
+
+    /// Returns true for the events created by [Accel::user_event].
+    fn is_user_event(&self) -> Result<bool, MCLError> {
+        let mut command: cl_sys::cl_command_type = 0;
+        let err = unsafe {
+            cl_sys::clGetEventInfo(
+                self.event,
+                cl_sys::CL_EVENT_COMMAND_TYPE,
+                std::mem::size_of::<cl_sys::cl_command_type>(),
+                &mut command as *mut cl_sys::cl_command_type as *mut cl_sys::c_void,
+                std::ptr::null_mut(),
+            )
+        };
+        check_cl_error(err, "clGetEventInfo")?;
+        Ok(command == cl_sys::CL_COMMAND_USER)
+    }
+}
+
+impl Clone for Event {
+    fn clone(&self) -> Self {
+        let err = unsafe { cl_sys::clRetainEvent(self.event) };
+        assert_eq!(err, cl_sys::CL_SUCCESS, "{}", crate::error_text(err));
+        Event { event: self.event }
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        let err = unsafe { cl_sys::clReleaseEvent(self.event) };
+        assert_eq!(err, cl_sys::CL_SUCCESS, "{}", crate::error_text(err));
+    }
+}
+
+/// Event completed by the host, created with [Accel::user_event].
+/// It gates the device commands that wait for it until the host
+/// calls [set_complete](UserEvent::set_complete), possibly from
+/// another thread.
+/// It dereferences to an [Event] for the wait lists.
+/// If it is dropped before being completed, it is marked as failed,
+/// so that the commands waiting for it are aborted instead of hanging.
+#[derive(Debug)]
+pub struct UserEvent(Event);
+
+impl UserEvent {
+    /// Marks the event as complete: the commands waiting for it can run.
+    pub fn set_complete(&self) -> Result<(), MCLError> {
+        let err = unsafe { cl_sys::clSetUserEventStatus(self.0.event, cl_sys::CL_COMPLETE) };
+        check_cl_error(err, "clSetUserEventStatus")
+    }
+
+    /// Marks the event as failed, with a negative error code:
+    /// the commands waiting for it are aborted.
+    pub fn set_failed(&self, code: cl_sys::cl_int) -> Result<(), MCLError> {
+        if code >= 0 {
+            return Err(MCLError::UserEventStatus(code));
+        }
+        let err = unsafe { cl_sys::clSetUserEventStatus(self.0.event, code) };
+        check_cl_error(err, "clSetUserEventStatus")
+    }
+}
+
+impl Drop for UserEvent {
+    fn drop(&mut self) {
+        // the commands waiting for the event would never run
+        if self.0.status().is_ok_and(EventStatus::is_pending) {
+            log::warn!("User event dropped before completion, marked as failed");
+            unsafe { cl_sys::clSetUserEventStatus(self.0.event, cl_sys::CL_INVALID_OPERATION) };
+        }
+    }
+}
+
+impl std::ops::Deref for UserEvent {
+    type Target = Event;
+    fn deref(&self) -> &Event {
+        &self.0
+    }
+}
+
+impl Accel {
+    /// Creates an event whose completion is decided by the host.
+    ///
+    /// While the event is pending, it gates the commands waiting for it,
+    /// and also all the commands enqueued after them, since the queue is
+    /// in order. The blocking calls ([finish](Accel::finish), the maps,
+    /// the transfers, [release_buffer](Accel::release_buffer) and
+    /// [into_vec](Accel::into_vec)) then wait until the event is completed:
+    /// if the same thread has to complete it, they never return.
+    /// The event has to be completed by another thread in that case.
+    /// # Examples
+    /// ```no_run
+    /// # let source = "__kernel void k(void){}".to_string();
+    /// let mut cldev = minicl::Accel::new(source, 0)?;
+    /// cldev.register_kernel("k")?;
+    /// let ready = cldev.user_event()?;
+    /// // the kernel is queued but does not start before `ready`
+    /// let run = unsafe { cldev.run_kernel_after("k", 64, 16, &[&ready])? };
+    /// // ... prepare things on the host ...
+    /// ready.set_complete()?;
+    /// run.wait()?;
+    /// # Ok::<(), minicl::MCLError>(())
+    /// ```
+    pub fn user_event(&self) -> Result<UserEvent, MCLError> {
+        let mut err: i32 = 0;
+        let event = unsafe { cl_sys::clCreateUserEvent(self.context, &mut err) };
+        check_cl_error(err, "clCreateUserEvent")?;
+        Ok(UserEvent(Event { event }))
+    }
+
+    /// Makes all the commands enqueued after this call wait for the
+    /// events (kernels, copies, fills, maps and transfers).
+    /// The blocking operations, such as the maps, wait for the events too:
+    /// a user event must be completed by another thread in that case.
+    pub fn enqueue_barrier(&mut self, wait_list: &[&Event]) -> Result<Event, MCLError> {
+        self.record_gates(wait_list)?;
+        let events = raw_events(wait_list);
+        let mut event: cl_sys::cl_event = std::ptr::null_mut();
+        let err = unsafe {
+            cl_sys::clEnqueueBarrierWithWaitList(
+                self.queue,
+                events.len() as u32,
+                events_ptr(&events),
+                &mut event,
+            )
+        };
+        check_cl_error(err, "clEnqueueBarrierWithWaitList")?;
+        Ok(Event { event })
+    }
+
+    /// Enqueues an empty command, complete when the previous commands
+    /// and the wait list are complete.
+    pub(crate) fn marker(&self, wait_list: &[&Event]) -> Result<Event, MCLError> {
+        let events = raw_events(wait_list);
+        let mut event: cl_sys::cl_event = std::ptr::null_mut();
+        let err = unsafe {
+            cl_sys::clEnqueueMarkerWithWaitList(
+                self.queue,
+                events.len() as u32,
+                events_ptr(&events),
+                &mut event,
+            )
+        };
+        check_cl_error(err, "clEnqueueMarkerWithWaitList")?;
+        self.complete(Event { event })
+    }
+
+    /// Waits for the event if the [Accel] is in blocking mode, unless
+    /// the queue is gated by a pending user event: waiting would then
+    /// block the thread that has to complete it.
+    pub(crate) fn complete(&self, event: Event) -> Result<Event, MCLError> {
+        if self.blocking && !self.gated()? {
+            event.wait()?;
+        }
+        Ok(event)
+    }
+
+    /// Keeps the pending user events of a wait list. Since the queue is
+    /// in order, they gate all the commands enqueued after them.
+    pub(crate) fn record_gates(&mut self, wait_list: &[&Event]) -> Result<(), MCLError> {
+        let mut gates = vec![];
+        for gate in self.gates.drain(..) {
+            if gate.status()?.is_pending() {
+                gates.push(gate);
+            }
+        }
+        for &event in wait_list {
+            if event.is_user_event()? && event.status()?.is_pending() {
+                gates.push(event.clone());
+            }
+        }
+        self.gates = gates;
+        Ok(())
+    }
+
+    /// Returns true if a recorded user event is still pending.
+    fn gated(&self) -> Result<bool, MCLError> {
+        for gate in &self.gates {
+            if gate.status()?.is_pending() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Marks the pending user events as failed, so that the
+    /// commands gated by them are aborted.
+    pub(crate) fn fail_gates(&mut self) {
+        for gate in self.gates.drain(..) {
+            if gate.status().is_ok_and(EventStatus::is_pending) {
+                log::warn!("User event still pending, marked as failed");
+                unsafe { cl_sys::clSetUserEventStatus(gate.event, cl_sys::CL_INVALID_OPERATION) };
+            }
+        }
+    }
+}
+
+/// OpenCL handles of a wait list.
+pub(crate) fn raw_events(wait_list: &[&Event]) -> Vec<cl_sys::cl_event> {
+    wait_list.iter().map(|e| e.event).collect()
+}
+
+/// Pointer to a wait list, null if the list is empty (as required by OpenCL).
+pub(crate) fn events_ptr(events: &[cl_sys::cl_event]) -> *const cl_sys::cl_event {
+    if events.is_empty() {
+        std::ptr::null()
+    } else {
+        events.as_ptr()
+    }
+}
+
+// some unit tests
+#[test]
+fn test_event_status() {
+    assert_eq!(EventStatus::from_code(cl_sys::CL_COMPLETE), EventStatus::Complete);
+    assert_eq!(EventStatus::from_code(cl_sys::CL_QUEUED), EventStatus::Queued);
+    assert_eq!(EventStatus::from_code(-5), EventStatus::Failed(-5));
+    assert!(EventStatus::Submitted.is_pending());
+    assert!(!EventStatus::Failed(-5).is_pending());
+}
@@ -36,130 +36,303 @@
 //!     minicl::kernel_set_args_and_run!(cldev, kname, globsize, locsize, v, x)?;
 //!
 //!     // map the buffer for access from the host
-//!     let v: Vec<i32> = cldev.map_buffer(v)?;
-//!     println!("First kernel run v={:?}", v);
+//!     let mapped = cldev.map_buffer(v)?;
+//!     println!("First kernel run v={:?}", &mapped[..]);
 //!
 //!     // unmap for giving it back to the device
-//!     let v = cldev.unmap_buffer(v)?;
+//!     // (dropping the guard also unmaps the buffer)
+//!     mapped.unmap()?;
 //!
 //!     // next call
 //!     minicl::kernel_set_args_and_run!(cldev, kname, globsize, locsize, v, x)?;
 //!
-//!     let v: Vec<i32> = cldev.map_buffer(v)?;
+//!     let v: Vec<i32> = cldev.map_buffer(v)?.to_vec();
 //!     println!("Next kernel run v={:?}", v);
 //!     Ok(())
 //! }
 //! ```
 use std::collections::HashMap;
-use std::alloc::Layout;
-#[derive(Debug)]
-pub enum MCLError {
-    OpenCl(String),
-    StdCString(std::ffi::NulError),
-    Other(String),
-}
-
-impl From<std::ffi::NulError> for MCLError {
-    fn from(err: std::ffi::NulError) -> Self {
-        MCLError::StdCString(err)
-    }
-}
+use std::convert::TryFrom;
 
-// Helper to convert CL error codes to Result
-fn check_cl_error(err: cl_sys::cl_int) -> Result<(), MCLError> {
-    if err == cl_sys::CL_SUCCESS {
-        Ok(())
-    } else {
-        Err(MCLError::OpenCl(error_text(err).to_string()))
-    }
-}
+mod device;
+pub use device::{platforms, Device, DeviceInfo, DeviceType, Platform, PlatformInfo};
+mod error;
+use error::check_cl_error;
+pub use error::{error_text, MCLError};
+mod program;
+pub use program::{parse_build_log, BuildDiagnostic, BuildOptions};
+use program::Program;
+mod cache;
+pub use cache::clear_cache;
+mod event;
+pub use event::{Event, EventStatus, UserEvent};
+mod ndrange;
+pub use ndrange::{LocalSize, NDRange};
+mod buffer;
+use buffer::BufferEntry;
+pub use buffer::{Buffer, MappedBuffer, MemFlags, Pod, ReadMappedBuffer};
+pub mod source;
+pub use source::{KernelSource, Precision, SourceLoader};
 
 /// All the OpenCL things (device, context, buffers, etc.)
 ///  are packed into a single Accelerator struct.
 #[derive(Debug)]
 pub struct Accel {
     context: cl_sys::cl_context,
-    device: cl_sys::cl_device_id,
-    program: cl_sys::cl_program,
+    device: Device,
+    programs: HashMap<String, Program>,
+    cache_dir: Option<std::path::PathBuf>,
     queue: cl_sys::cl_command_queue,
     kernels: HashMap<String, cl_sys::cl_kernel>,
-    buffers: HashMap<*mut cl_sys::c_void, (cl_sys::cl_mem, usize, usize, bool, Layout)>,
+    buffers: HashMap<usize, BufferEntry>,
+    next_buffer: usize,
+    id: usize,
+    max_work_item_sizes: Vec<usize>,
+    max_work_group_size: usize,
+    padded_kernels: HashMap<String, PaddedKernel>,
+    blocking: bool,
+    /// Pending user events given in wait lists.
+    gates: Vec<Event>,
 }
 
-impl Accel {
-    /// Generates a minicl environment
-    /// from an OpenCL source code and a platform id.
-    pub fn new(oclsource: String, numplat: usize) -> Result<Accel, MCLError> {
-        let platform: cl_sys::cl_platform_id = std::ptr::null_mut();
-        let mut nb_platforms: u32 = 0;
-        let mut device: cl_sys::cl_device_id = std::ptr::null_mut();
-        
-        let err = unsafe { cl_sys::clGetPlatformIDs(0, std::ptr::null_mut(), &mut nb_platforms) };
-        check_cl_error(err)?;
-        
-        println!("Found {} platform(s)", nb_platforms);
-        if nb_platforms == 0 {
-             return Err(MCLError::Other("No OpenCL platforms found.".to_string()));
+/// Padding of the global size of a kernel, with the kernel properties
+/// queried once by [pad_global_size](Accel::pad_global_size).
+#[derive(Debug, Clone, Copy)]
+struct PaddedKernel {
+    size_arg: usize,
+    num_args: usize,
+    work_group_size: usize,
+    preferred_multiple: usize,
+}
+
+/// Source of the unique ids of the [Accel] objects.
+static NEXT_ACCEL_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Name of the program built from the source given at the creation of an [Accel].
+pub const MAIN_PROGRAM: &str = "main";
+
+/// Builder for selecting the OpenCL device of an [Accel].
+///
+/// By default, the first GPU or CPU device found on any platform
+/// is selected. The criteria are cumulative.
+/// # Examples
+/// ```no_run
+/// # let source = "__kernel void k(void){}".to_string();
+/// let cldev = minicl::Accel::builder()
+///     .platform(0)
+///     .device_type(minicl::DeviceType::GPU)
+///     .device_index(1)
+///     .build(source)?;
+/// # Ok::<(), minicl::MCLError>(())
+/// ```
+pub struct AccelBuilder {
+    platform: Option<usize>,
+    device_type: DeviceType,
+    device_index: usize,
+    name: Option<String>,
+    vendor: Option<String>,
+    filter: Option<DeviceFilter>,
+    options: BuildOptions,
+    include_dirs: Vec<std::path::PathBuf>,
+    cache_dir: Option<std::path::PathBuf>,
+}
+
+type DeviceFilter = Box<dyn Fn(&Device) -> bool>;
+
+impl Default for AccelBuilder {
+    fn default() -> Self {
+        AccelBuilder {
+            platform: None,
+            device_type: DeviceType::GPU | DeviceType::CPU,
+            device_index: 0,
+            name: None,
+            vendor: None,
+            filter: None,
+            options: BuildOptions::new().no_warnings(),
+            include_dirs: vec![],
+            cache_dir: None,
         }
+    }
+}
 
-        let mut platform = vec![platform; nb_platforms as usize];
-        let err =
-            unsafe { cl_sys::clGetPlatformIDs(nb_platforms, &mut platform[0], &mut nb_platforms) };
-        check_cl_error(err)?;
+impl AccelBuilder {
+    /// Restricts the search to the platform with the given index.
+    /// All the platforms are searched if not set.
+    pub fn platform(mut self, numplat: usize) -> Self {
+        self.platform = Some(numplat);
+        self
+    }
 
-        if numplat >= nb_platforms as usize {
-             return Err(MCLError::Other(format!("Platform index {} out of range ({} found)", numplat, nb_platforms)));
+    /// Restricts the search to the given device type(s).
+    pub fn device_type(mut self, device_type: DeviceType) -> Self {
+        self.device_type = device_type;
+        self
+    }
+
+    /// Selects the n-th device among the matching ones.
+    pub fn device_index(mut self, index: usize) -> Self {
+        self.device_index = index;
+        self
+    }
+
+    /// Only keeps the devices whose name contains `name` (case insensitive).
+    pub fn device_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_lowercase());
+        self
+    }
+
+    /// Only keeps the devices whose vendor contains `vendor` (case insensitive).
+    pub fn vendor(mut self, vendor: &str) -> Self {
+        self.vendor = Some(vendor.to_lowercase());
+        self
+    }
+
+    /// Only keeps the devices for which the predicate returns true.
+    pub fn filter<F: Fn(&Device) -> bool + 'static>(mut self, predicate: F) -> Self {
+        self.filter = Some(Box::new(predicate));
+        self
+    }
+
+    /// Sets the options for compiling the OpenCL source.
+    /// The default is [no_warnings](BuildOptions::no_warnings).
+    pub fn options(mut self, options: BuildOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Adds a directory to the search path of the `#include` directives,
+    /// for [build_from_files](AccelBuilder::build_from_files).
+    pub fn include_dir<P: AsRef<std::path::Path>>(mut self, dir: P) -> Self {
+        self.include_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Enables the on-disk cache of the program binaries in `dir`.
+    /// The cached binary is used when the source, the options, the device
+    /// and its driver are unchanged. Otherwise the source is compiled.
+    pub fn cache_dir<P: AsRef<std::path::Path>>(mut self, dir: P) -> Self {
+        self.cache_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Returns all the devices matching the criteria, in platform order.
+    pub fn matching_devices(&self) -> Result<Vec<Device>, MCLError> {
+        let platforms = device::platform_ids()?;
+        if platforms.is_empty() {
+            return Err(MCLError::PlatformNotFound(
+                "No OpenCL platforms found.".to_string(),
+            ));
         }
-        
-        let mut size: usize = 0;
-        let err = unsafe {
-            cl_sys::clGetPlatformInfo(
-                platform[numplat],
-                cl_sys::CL_PLATFORM_VENDOR,
-                0,
-                std::ptr::null_mut(),
-                &mut size,
-            )
+        let platforms = match self.platform {
+            Some(numplat) => {
+                if numplat >= platforms.len() {
+                    return Err(MCLError::PlatformNotFound(format!(
+                        "Platform index {} out of range ({} found)",
+                        numplat,
+                        platforms.len()
+                    )));
+                }
+                vec![platforms[numplat]]
+            }
+            None => platforms,
         };
-        check_cl_error(err)?;
-        
-        if size == 0 {
-            return Err(MCLError::Other("Platform name size is 0".to_string()));
+
+        let mut devices = vec![];
+        for platform in platforms {
+            for dev in device::device_ids(platform, self.device_type)? {
+                if let Some(name) = &self.name {
+                    if !dev.name()?.to_lowercase().contains(name.as_str()) {
+                        continue;
+                    }
+                }
+                if let Some(vendor) = &self.vendor {
+                    if !dev.vendor()?.to_lowercase().contains(vendor.as_str()) {
+                        continue;
+                    }
+                }
+                if let Some(filter) = &self.filter {
+                    if !filter(&dev) {
+                        continue;
+                    }
+                }
+                devices.push(dev);
+            }
         }
+        Ok(devices)
+    }
 
-        let platform_name = vec![1; size];
-        let platform_name = String::from_utf8(platform_name).unwrap(); // Keeping unwraps for UTF8 as unlikely for now, can improve later
-        let platform_name = std::ffi::CString::new(platform_name)?; 
-        
-        let err = unsafe {
-            cl_sys::clGetPlatformInfo(
-                platform[numplat],
-                cl_sys::CL_PLATFORM_VENDOR,
-                size,
-                platform_name.as_ptr() as *mut cl_sys::c_void,
-                &mut size,
-            )
-        };
-        check_cl_error(err)?;
-        
-        let platform_name = unsafe {
-            std::ffi::CStr::from_ptr(platform_name.as_ptr())
-                .to_string_lossy()
-                .into_owned()
-        };
-        println!("Platform: {}", platform_name);
+    /// Generates a minicl environment on the selected device
+    /// from an OpenCL source code.
+    pub fn build(self, oclsource: String) -> Result<Accel, MCLError> {
+        let devices = self.matching_devices()?;
+        let dev = *devices.get(self.device_index).ok_or_else(|| {
+            MCLError::DeviceNotFound(format!(
+                "Device index {} out of range ({} matching device(s) found)",
+                self.device_index,
+                devices.len()
+            ))
+        })?;
+        Accel::create(oclsource, dev, &self.options, self.cache_dir)
+    }
 
-        let mut temp: u32 = 0;
-        let err = unsafe {
-            cl_sys::clGetDeviceIDs(
-                platform[numplat],
-                cl_sys::CL_DEVICE_TYPE_GPU | cl_sys::CL_DEVICE_TYPE_CPU,
-                1,
-                &mut device,
-                &mut temp,
-            )
-        };
-        check_cl_error(err)?;
+    /// Generates a minicl environment on the selected device
+    /// from OpenCL source files. The files are concatenated and
+    /// their `#include` directives are resolved by minicl.
+    pub fn build_from_files<P: AsRef<std::path::Path>>(
+        self,
+        paths: &[P],
+    ) -> Result<Accel, MCLError> {
+        let loader = self
+            .include_dirs
+            .iter()
+            .fold(SourceLoader::new(), |loader, dir| loader.include_dir(dir));
+        let oclsource = loader.load_all(paths)?;
+        self.build(oclsource)
+    }
+}
+
+impl Accel {
+    /// Generates a minicl environment
+    /// from an OpenCL source code and a platform id.
+    /// The first GPU or CPU device of the platform is used.
+    /// See [builder](Accel::builder) for a finer device selection.
+    pub fn new(oclsource: String, numplat: usize) -> Result<Accel, MCLError> {
+        Accel::builder().platform(numplat).build(oclsource)
+    }
+
+    /// Generates a minicl environment
+    /// from OpenCL source files and a platform id.
+    /// See [build_from_files](AccelBuilder::build_from_files).
+    pub fn from_files<P: AsRef<std::path::Path>>(
+        paths: &[P],
+        numplat: usize,
+    ) -> Result<Accel, MCLError> {
+        Accel::builder().platform(numplat).build_from_files(paths)
+    }
+
+    /// Returns a builder for selecting the device.
+    pub fn builder() -> AccelBuilder {
+        AccelBuilder::default()
+    }
+
+    /// Generates a minicl environment on a given device
+    /// from an OpenCL source code.
+    pub fn with_device(oclsource: String, dev: Device) -> Result<Accel, MCLError> {
+        Accel::create(oclsource, dev, &BuildOptions::new().no_warnings(), None)
+    }
+
+    fn create(
+        oclsource: String,
+        dev: Device,
+        options: &BuildOptions,
+        cache_dir: Option<std::path::PathBuf>,
+    ) -> Result<Accel, MCLError> {
+        let platform_name = device::platform_info_string(dev.platform, cl_sys::CL_PLATFORM_VENDOR)?;
+        log::info!("Platform: {}", platform_name);
+        log::info!("Device: {}", dev.name()?);
+        let max_work_item_sizes = dev.max_work_item_sizes()?;
+        let max_work_group_size = dev.max_work_group_size()?;
+        let device = dev.id;
 
         let mut err: i32 = 0;
         let context = unsafe {
@@ -172,7 +345,7 @@ impl Accel {
                 &mut err,
             )
         };
-        check_cl_error(err)?;
+        check_cl_error(err, "clCreateContext")?;
 
         let mut err: i32 = 0;
         let queue = unsafe {
@@ -183,336 +356,442 @@ impl Accel {
                 &mut err,
             )
         };
-        check_cl_error(err)?;
-
-        let mut err: i32 = 0;
-        let oclsource = std::ffi::CString::new(oclsource)?;
-        //let oclsources = [oclsource.as_ptr()];
-        let program = unsafe {
-            cl_sys::clCreateProgramWithSource(
-                context,
-                1,
-                &(oclsource.as_ptr()) as *const *const cl_sys::libc::c_char,
-                std::ptr::null(),
-                &mut err,
-            )
-        };
-        check_cl_error(err)?;
-
-        let opt = std::ffi::CString::new("-w")?;
-        let log: *mut cl_sys::c_void = std::ptr::null_mut();
-        let errb = unsafe { cl_sys::clBuildProgram(program, 1, &device, opt.as_ptr(), None, log) };
-
-        // first get the size of the build log
-        let mut size = 0;
-        let err = unsafe {
-            cl_sys::clGetProgramBuildInfo(
-                program,
-                device,
-                cl_sys::CL_PROGRAM_BUILD_LOG,
-                0,
-                std::ptr::null_mut(),
-                &mut size,
-            )
-        };
-        check_cl_error(err)?; // We want to see this error even if build failed
-        
-        println!("Size of build log: {}", size);
-        // then get the build log
-        let log = vec![1; size];
-        let log = String::from_utf8(log).unwrap();
-        //let log: *mut cl_sys::c_void = std::ptr::null_mut();
-        let log = std::ffi::CString::new(log)?;
-
-        let err = unsafe {
-            cl_sys::clGetProgramBuildInfo(
-                program,
-                device,
-                cl_sys::CL_PROGRAM_BUILD_LOG,
-                size,
-                log.as_ptr() as *mut cl_sys::c_void,
-                &mut size,
-            )
-        };
-        check_cl_error(err)?;
+        check_cl_error(err, "clCreateCommandQueue")?;
 
-        let log = unsafe {
-            std::ffi::CStr::from_ptr(log.as_ptr())
-                .to_string_lossy()
-                .into_owned()
+        let options = options.to_string();
+        let result =
+            program::build_program(context, &dev, &oclsource, &options, cache_dir.as_deref());
+        let (program, build_log) = match result {
+            Ok(built) => built,
+            Err(e) => {
+                unsafe {
+                    cl_sys::clReleaseCommandQueue(queue);
+                    cl_sys::clReleaseContext(context);
+                }
+                return Err(e);
+            }
         };
-        println!("Build messages:\n-------------------------------------");
-        println!("{}", log);
-        println!("-------------------------------------");
-        
-        check_cl_error(errb)?;
 
+        let mut programs = HashMap::new();
+        programs.insert(MAIN_PROGRAM.to_string(), Program { program, build_log });
         Ok(Accel {
             context,
-            device,
-            program,
+            device: dev,
+            programs,
+            cache_dir,
             queue,
             kernels: HashMap::new(),
             buffers: HashMap::new(),
+            next_buffer: 0,
+            id: NEXT_ACCEL_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            max_work_item_sizes,
+            max_work_group_size,
+            padded_kernels: HashMap::new(),
+            blocking: false,
+            gates: vec![],
         })
     }
 
-    /// Registers a kernel, before it can be called.
+    /// Returns the device used by this environment.
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    /// Returns the messages of the OpenCL compiler for the main program.
+    /// The build log is also sent to the [log] facade, at the info level.
+    pub fn build_log(&self) -> &str {
+        &self.programs[MAIN_PROGRAM].build_log
+    }
+
+    /// Returns the messages of the OpenCL compiler for a given program.
+    pub fn program_build_log(&self, program: &str) -> Result<&str, MCLError> {
+        self.programs
+            .get(program)
+            .map(|p| p.build_log.as_str())
+            .ok_or_else(|| MCLError::ProgramNotFound(program.to_string()))
+    }
+
+    /// Compiles an additional program in the same context. Its kernels can be
+    /// registered with [register_kernel_from](Accel::register_kernel_from)
+    /// and share the already registered buffers.
+    pub fn add_program(
+        &mut self,
+        name: &str,
+        oclsource: String,
+        options: &BuildOptions,
+    ) -> Result<(), MCLError> {
+        if self.programs.contains_key(name) {
+            return Err(MCLError::ProgramAlreadyRegistered(name.to_string()));
+        }
+        let (program, build_log) = program::build_program(
+            self.context,
+            &self.device,
+            &oclsource,
+            &options.to_string(),
+            self.cache_dir.as_deref(),
+        )?;
+        self.programs
+            .insert(name.to_string(), Program { program, build_log });
+        Ok(())
+    }
+
+    /// Removes the cached program binaries, if the cache is enabled.
+    /// The programs already built are not affected.
+    pub fn clear_cache(&self) -> Result<(), MCLError> {
+        match &self.cache_dir {
+            Some(dir) => clear_cache(dir),
+            None => Ok(()),
+        }
+    }
+
+    /// Registers a kernel of the main program, before it can be called.
     pub fn register_kernel(&mut self, name: &str) -> Result<(), MCLError> {
+        self.register_kernel_from(MAIN_PROGRAM, name)
+    }
+
+    /// Registers a kernel of a given program, before it can be called.
+    /// Kernel names must be unique among all the programs.
+    pub fn register_kernel_from(&mut self, program: &str, name: &str) -> Result<(), MCLError> {
         if self.kernels.contains_key(name) {
-            return Err(MCLError::Other(format!("Kernel '{}' already registered.", name)));
+            return Err(MCLError::KernelAlreadyRegistered(name.to_string()));
         }
+        let program = self
+            .programs
+            .get(program)
+            .ok_or_else(|| MCLError::ProgramNotFound(program.to_string()))?;
         let mut err: i32 = 0;
         let cname = std::ffi::CString::new(name.to_string())?;
         let kernel: cl_sys::cl_kernel =
-            unsafe { cl_sys::clCreateKernel(self.program, cname.as_ptr(), &mut err) };
-        check_cl_error(err)?;
+            unsafe { cl_sys::clCreateKernel(program.program, cname.as_ptr(), &mut err) };
+        check_cl_error(err, "clCreateKernel")
+            .map_err(|e| e.with_context(format!("kernel '{}'", name)))?;
         //println!("kernel={:?}", kernel);
         self.kernels.insert(name.to_string(), kernel);
         Ok(())
     }
 
-    /// Registers a buffer before it can be passed to a kernel.
-    /// The buffer is automatically copied on the device. The memory is
-    /// managed by OpenCL until the next map.
-    /// It can not be accessed by the host until the next map.
-    /// Returns a pointer to the memory zone managed by OpenCL.
-    pub fn register_buffer<T>(&mut self, mut v: Vec<T>) -> Result<*mut cl_sys::c_void, MCLError> {
-        v.shrink_to_fit();
-        if v.len() != v.capacity() {
-             return Err(MCLError::Other("Vector length must match capacity for buffer registration".to_string()));
-        }
-        let ptr0 = v.as_mut_ptr() as *mut cl_sys::c_void;
-        if self.buffers.contains_key(&ptr0) {
-             return Err(MCLError::Other("Buffer already registered.".to_string()));
-        }
-        let n = v.len();
-        // leave deallocation duty to MiniCL
-        std::mem::forget(v);
-        let szf = std::mem::size_of::<T>();
-        let mut err: i32 = 0;
-        let buffer: cl_sys::cl_mem = unsafe {
-            cl_sys::clCreateBuffer(
-                self.context,
-                cl_sys::CL_MEM_READ_WRITE | cl_sys::CL_MEM_USE_HOST_PTR,
-                n * szf,
-                ptr0,
-                &mut err,
-            )
-        };
-        check_cl_error(err)?;
-        let is_map = false;
-        let layout = std::alloc::Layout::new::<T>();
-        self.buffers.insert(ptr0, (buffer, n * szf, szf, is_map, layout));
-        Ok(ptr0)
-    }
-
-    /// Unmaps back to the device a buffer mapped on the host.
-    /// The buffer must have been registered before.
-    pub fn unmap_buffer<T>(&mut self, mut v: Vec<T>) -> Result<*mut cl_sys::c_void, MCLError> {
-        v.shrink_to_fit();
-        if v.len() != v.capacity() {
-            return Err(MCLError::Other("Vector length mismatch during unmap".to_string()));
-        }
-        let ptr0 = v.as_mut_ptr() as *mut cl_sys::c_void;
-        if !self.buffers.contains_key(&ptr0) {
-            return Err(MCLError::Other("Buffer not registered or already dropped.".to_string()));
-        }
-
-        let tup = self.buffers.get(&ptr0).unwrap();
-        let buffer = tup.0;
-        let size = tup.1;
-        let szf = tup.2;
-        let is_map = tup.3;
-        let layout = tup.4;
-        
-        if !is_map {
-             return Err(MCLError::Other("Buffer is already unmapped.".to_string()));
-        }
-       
-        // Safety Check: Detect if the user reallocated the vector
-        let current_ptr = v.as_ptr() as *mut cl_sys::c_void;
-        if current_ptr != ptr0 {
-             return Err(MCLError::Other("Error: The buffer has been reallocated (pointer changed). \
-                    Cannot unmap a buffer that has moved in memory. \
-                    Did you push/resize the vector while it was mapped?".to_string()));
-        }
-       
-        let needed_capacity = size / szf;
-        if v.capacity() < needed_capacity {
-             return Err(MCLError::Other("Error: The buffer capacity has changed significantly. \
-                    This suggests a reallocation or logic error.".to_string()));
-        }
-
-        self.buffers.remove(&ptr0).unwrap();
-        std::mem::forget(v);
-        
-        let err = unsafe {
-            cl_sys::clEnqueueUnmapMemObject(
-                self.queue,
-                buffer,
-                ptr0,
-                0,
-                std::ptr::null(),
-                std::ptr::null_mut(),
-            )
-        };
-        check_cl_error(err)?;
-        let is_map = false;
-        self.buffers.insert(ptr0, (buffer, size, szf, is_map, layout));
-        Ok(ptr0)
-    }
-
-    /// Maps a buffer from the device to the host.
-    /// Must be called before any access to the buffer
-    /// from the host side.
-    pub fn map_buffer<T>(&mut self, ptr0: *mut cl_sys::c_void) -> Result<Vec<T>, MCLError> {
-        let mut err = 0;
-        let blocking = cl_sys::CL_TRUE;
-        //let szf = std::mem::size_of::<T>();
-        //let toto = self.buffers.get(&name).unwrap();
-        if !self.buffers.contains_key(&ptr0) {
-             return Err(MCLError::Other("Buffer not registered".to_string()));
-        }
-        let tup = self.buffers.get(&ptr0).unwrap();
-        let buffer = tup.0;
-        let size = tup.1;
-        let szf = tup.2;
-        let is_map = tup.3;
-        let layout = tup.4;
-        
-        if is_map {
-             return Err(MCLError::Other("Buffer already mapped.".to_string()));
-        }
-
-        let ptr = unsafe {
-            cl_sys::clEnqueueMapBuffer(
-                self.queue,
-                buffer,
-                blocking,
-                cl_sys::CL_MAP_READ | cl_sys::CL_MAP_WRITE,  
-                0,
-                size,
-                0,
-                std::ptr::null(),
-                std::ptr::null_mut(),
-                &mut err,
-            )
-        } as *mut T;
-        check_cl_error(err)?;
-        self.buffers.remove(&ptr0);
-        let is_map = true;
-        self.buffers.insert(ptr0, (buffer, size, szf, is_map, layout));
-        let n = size / szf;
-        //println!("size={} szf={}", size, szf);
-        if size % szf != 0 {
-             return Err(MCLError::Other("Possible type mismatch (size not divisible by type size)".to_string()));
-        }
-        
-        if ptr != ptr0 as *mut T {
-             // This is a strict internal consistency check, maybe panic represents a totally broken state here? 
-             // But let's return Error for consistency.
-             return Err(MCLError::Other("OpenCL returned a different pointer than the hosted one. MiniCL logic broken for CL_MEM_USE_HOST_PTR.".to_string()));
-        }
-
-        let v: Vec<T> = unsafe { Vec::from_raw_parts(ptr, n, n) };
-        Ok(v)
-    }
-
     /// Defines kernel args value/location before kernel call.
     /// The args must implement the TrueArg traits, which converts
     /// the Rust arg type to the corresponding OpenCL type, with the
     /// same size.
     pub fn set_kernel_arg<T: TrueArg>(&mut self, kname: &str, index: usize, arg: &T) -> Result<(), MCLError> {
-        let kernel = self.kernels.get(kname).ok_or(MCLError::Other(format!("Kernel '{}' not found", kname)))?;
+        let kernel = self
+            .kernels
+            .get(kname)
+            .ok_or_else(|| MCLError::KernelNotFound(kname.to_string()))?;
         let smem = arg.arg_size();
         // Check if argument is safe to use (not mapped)
         let targ = arg.true_arg(self)?;
         let err = unsafe { cl_sys::clSetKernelArg(*kernel, index as u32, smem, targ) };
-        check_cl_error(err)?;
+        check_cl_error(err, "clSetKernelArg")
+            .map_err(|e| e.with_context(format!("kernel '{}', argument {}", kname, index)))?;
         Ok(())
     }
 
     /// Sets a local memory argument for a kernel.
     /// `size` is the number of bytes to allocate in local memory.
     pub fn set_kernel_local_arg(&mut self, kname: &str, index: usize, size: usize) -> Result<(), MCLError> {
-        let kernel = self.kernels.get(kname).ok_or(MCLError::Other(format!("Kernel '{}' not found", kname)))?;
+        let kernel = self
+            .kernels
+            .get(kname)
+            .ok_or_else(|| MCLError::KernelNotFound(kname.to_string()))?;
         let err = unsafe { cl_sys::clSetKernelArg(*kernel, index as u32, size, std::ptr::null()) };
-        check_cl_error(err)?;
+        check_cl_error(err, "clSetKernelArg")
+            .map_err(|e| e.with_context(format!("kernel '{}', argument {}", kname, index)))?;
         Ok(())
     }
 
     /// Runs a kernel with given global size and local size.
+    /// The sizes are `usize` for 1D launches, or arrays `[usize; 2]` and
+    /// `[usize; 3]` for 2D and 3D launches (see [NDRange]).
+    /// The local size can be `None` for letting the driver choose it
+    /// (see [LocalSize]).
     /// Before calling this function, it is necessay to set the kernel args.
     /// This can be achieved with the function [set_kernel_arg](Accel::set_kernel_arg).
+    ///
+    /// The kernel is only enqueued: the function returns without waiting for
+    /// its end, unless [set_blocking](Accel::set_blocking) has been called.
+    /// The maps and the transfers wait for the previous kernels, and
+    /// [finish](Accel::finish) waits for all of them.
     /// # Safety
     /// This function is not safe, because if mem buffers are mapped to the host
     /// and used by the kernel, this can produce undefined behavior.
     /// It is better to use the macro [kernel_set_args_and_run!](kernel_set_args_and_run!), which recheck all args.
     /// The measured overhead is generally very very small.
-    pub unsafe fn run_kernel(&mut self, kname: &str, globsize: usize, locsize: usize) -> Result<(), MCLError> {
-        let kernel = self.kernels.get(kname).ok_or(MCLError::Other(format!("Kernel '{}' not found", kname)))?;
+    pub unsafe fn run_kernel<G: Into<NDRange>, L: Into<LocalSize>>(
+        &mut self,
+        kname: &str,
+        globsize: G,
+        locsize: L,
+    ) -> Result<Event, MCLError> {
+        self.run_kernel_after(kname, globsize, locsize, &[])
+    }
+
+    /// Same as [run_kernel](Accel::run_kernel), but the kernel does not
+    /// start before the end of the commands of the wait list
+    /// (possibly [user events](Accel::user_event)).
+    /// # Safety
+    /// See [run_kernel](Accel::run_kernel).
+    pub unsafe fn run_kernel_after<G: Into<NDRange>, L: Into<LocalSize>>(
+        &mut self,
+        kname: &str,
+        globsize: G,
+        locsize: L,
+        wait_list: &[&Event],
+    ) -> Result<Event, MCLError> {
+        let kernel = *self
+            .kernels
+            .get(kname)
+            .ok_or_else(|| MCLError::KernelNotFound(kname.to_string()))?;
+        self.record_gates(wait_list)?;
 
-        if globsize % locsize != 0 {
-             return Err(MCLError::Other(format!("Global size {} must be a multiple of local size {}", globsize, locsize)));
+        let (mut globsize, mut locsize) = (globsize.into(), locsize.into().0);
+        if let Some(&padded) = self.padded_kernels.get(kname) {
+            let local = match locsize {
+                Some(local) => local,
+                None => ndrange::auto_local_size(
+                    &globsize,
+                    padded.work_group_size,
+                    padded.preferred_multiple,
+                    &self.max_work_item_sizes,
+                ),
+            };
+            self.set_true_global_size(kname, &padded, &globsize)?;
+            globsize = globsize.round_up(&local);
+            locsize = Some(local);
         }
+        if let Some(locsize) = &locsize {
+            ndrange::check_work_sizes(
+                &globsize,
+                locsize,
+                &self.max_work_item_sizes,
+                self.max_work_group_size,
+            )?;
+        }
+        let local_ptr = locsize
+            .as_ref()
+            .map_or(std::ptr::null(), |l| l.sizes().as_ptr());
+        let events = event::raw_events(wait_list);
+        let mut event: cl_sys::cl_event = std::ptr::null_mut();
 
-        let offset = 0;
         #[allow(unused_unsafe)]
         let err = unsafe {
             cl_sys::clEnqueueNDRangeKernel(
                 self.queue,
-                *kernel,
-                1,
-                &offset,
-                &globsize,
-                &locsize,
-                0,
-                std::ptr::null(),
-                std::ptr::null_mut(),
+                kernel,
+                globsize.dim() as u32,
+                globsize.offset().as_ptr(),
+                globsize.sizes().as_ptr(),
+                local_ptr,
+                events.len() as u32,
+                event::events_ptr(&events),
+                &mut event,
             )
         };
-        check_cl_error(err)?;
+        check_cl_error(err, "clEnqueueNDRangeKernel")
+            .map_err(|e| e.with_context(format!("kernel '{}'", kname)))?;
+        self.complete(Event { event })
+            .map_err(|e| e.with_context(format!("kernel '{}'", kname)))
+    }
 
-        #[allow(unused_unsafe)]
+    /// Makes [run_kernel](Accel::run_kernel) wait for the end of each kernel
+    /// (the default is asynchronous runs), as well as the copies and fills.
+    /// The commands enqueued while a [user event](Accel::user_event) of a
+    /// wait list is pending are not waited for: the queue is in order, and
+    /// waiting would block the thread that has to complete the event.
+    pub fn set_blocking(&mut self, blocking: bool) {
+        self.blocking = blocking;
+    }
+
+    /// Sends all the enqueued commands to the device, without waiting.
+    pub fn flush(&self) -> Result<(), MCLError> {
+        let err = unsafe { cl_sys::clFlush(self.queue) };
+        check_cl_error(err, "clFlush")
+    }
+
+    /// Waits for the end of all the enqueued commands, including the ones
+    /// gated by [pending user events](Accel::user_event).
+    pub fn finish(&self) -> Result<(), MCLError> {
         let err = unsafe { cl_sys::clFinish(self.queue) };
-        check_cl_error(err)?;
+        check_cl_error(err, "clFinish")
+    }
+
+    /// Chooses a local size for running a kernel on the given global size,
+    /// from the work-group size limit of the kernel
+    /// (`CL_KERNEL_WORK_GROUP_SIZE`) and its preferred multiple
+    /// (`CL_KERNEL_PREFERRED_WORK_GROUP_SIZE_MULTIPLE`).
+    /// The global size may not be a multiple of the result: see
+    /// [pad_global_size](Accel::pad_global_size).
+    pub fn preferred_local_size<G: Into<NDRange>>(
+        &self,
+        kname: &str,
+        globsize: G,
+    ) -> Result<NDRange, MCLError> {
+        let (work_group_size, multiple) = self.work_group_info(kname)?;
+        Ok(ndrange::auto_local_size(
+            &globsize.into(),
+            work_group_size,
+            multiple,
+            &self.max_work_item_sizes,
+        ))
+    }
+
+    /// Work-group size limit of a kernel and its preferred multiple.
+    fn work_group_info(&self, kname: &str) -> Result<(usize, usize), MCLError> {
+        let kernel = *self
+            .kernels
+            .get(kname)
+            .ok_or_else(|| MCLError::KernelNotFound(kname.to_string()))?;
+        let info = |param| -> Result<usize, MCLError> {
+            let mut value: usize = 0;
+            let err = unsafe {
+                cl_sys::clGetKernelWorkGroupInfo(
+                    kernel,
+                    self.device.id,
+                    param,
+                    std::mem::size_of::<usize>(),
+                    &mut value as *mut usize as *mut cl_sys::c_void,
+                    std::ptr::null_mut(),
+                )
+            };
+            check_cl_error(err, "clGetKernelWorkGroupInfo")
+                .map_err(|e| e.with_context(format!("kernel '{}'", kname)))?;
+            Ok(value)
+        };
+        let work_group_size = info(cl_sys::CL_KERNEL_WORK_GROUP_SIZE)?;
+        let multiple = info(cl_sys::CL_KERNEL_PREFERRED_WORK_GROUP_SIZE_MULTIPLE)?;
+        Ok((work_group_size, multiple))
+    }
+
+    /// Enables (with `Some(size_arg)`) or disables (with `None`) the
+    /// padding of the global size of a kernel.
+    /// When enabled, the global size is rounded up to a multiple of the
+    /// local size (the [preferred one](Accel::preferred_local_size) if
+    /// the local size is automatic), and the true global size is passed
+    /// to the kernel in the arguments `size_arg`, `size_arg + 1`, ...,
+    /// one `int` per dimension.
+    /// The argument count and work-group properties of the kernel are
+    /// queried here, not at each run.
+    /// The kernel has to skip the work-items beyond the true size
+    /// (here with `size_arg = 1`):
+    /// ```c
+    /// __kernel void k(__global float *v, int nx, int ny, float x) {
+    ///     int i = get_global_id(0);
+    ///     int j = get_global_id(1);
+    ///     if (i >= nx || j >= ny) return;
+    ///     ...
+    /// }
+    /// ```
+    pub fn pad_global_size(&mut self, kname: &str, size_arg: Option<usize>) -> Result<(), MCLError> {
+        let size_arg = match size_arg {
+            Some(index) => index,
+            None => {
+                if !self.kernels.contains_key(kname) {
+                    return Err(MCLError::KernelNotFound(kname.to_string()));
+                }
+                self.padded_kernels.remove(kname);
+                return Ok(());
+            }
+        };
+        let (work_group_size, preferred_multiple) = self.work_group_info(kname)?;
+        let mut num_args: cl_sys::cl_uint = 0;
+        let err = unsafe {
+            cl_sys::clGetKernelInfo(
+                self.kernels[kname],
+                cl_sys::CL_KERNEL_NUM_ARGS,
+                std::mem::size_of::<cl_sys::cl_uint>(),
+                &mut num_args as *mut cl_sys::cl_uint as *mut cl_sys::c_void,
+                std::ptr::null_mut(),
+            )
+        };
+        check_cl_error(err, "clGetKernelInfo")
+            .map_err(|e| e.with_context(format!("kernel '{}'", kname)))?;
+        let padded = PaddedKernel {
+            size_arg,
+            num_args: num_args as usize,
+            work_group_size,
+            preferred_multiple,
+        };
+        self.padded_kernels.insert(kname.to_string(), padded);
+        Ok(())
+    }
+
+    /// Sets the true global size in the size arguments of a padded kernel.
+    fn set_true_global_size(
+        &mut self,
+        kname: &str,
+        padded: &PaddedKernel,
+        globsize: &NDRange,
+    ) -> Result<(), MCLError> {
+        if padded.size_arg + globsize.dim() > padded.num_args {
+            return Err(MCLError::PaddedSizeArgs {
+                kernel: kname.to_string(),
+                first: padded.size_arg,
+                dim: globsize.dim(),
+                num_args: padded.num_args,
+            });
+        }
+        let mut sizes = vec![];
+        for &size in globsize.sizes() {
+            let size = i32::try_from(size).map_err(|_| MCLError::GlobalSizeOverflow {
+                kernel: kname.to_string(),
+                global: *globsize,
+            })?;
+            sizes.push(size);
+        }
+        for (d, size) in sizes.iter().enumerate() {
+            self.set_kernel_arg(kname, padded.size_arg + d, size)?;
+        }
         Ok(())
     }
 }
 
 /// OpenCL memory is managed in a C-like fashion.
-/// All the buffers which have not yet been mapped back to the
-/// host must be carefully given back to Rust.
+/// The host memory of all the buffers belongs to MiniCL and is
+/// freed after the release of the OpenCL objects.
 impl Drop for Accel {
     fn drop(&mut self) {
-        println!("MiniCL memory drop");
-        for (ptr, (buffer, size, szf, is_map, layout)) in self.buffers.iter() {
-            if !is_map {
-                let _n = size / szf;
-                // n is not used anymore for deallocation
-                assert!(size % szf == 0);
-                println!("Free buffer {:?}", ptr);
-                
-                // Correctly deallocate memory using the stored layout
-                unsafe {
-                    std::alloc::dealloc(*ptr as *mut u8, *layout);
+        log::debug!("MiniCL memory drop");
+        // MiniCL owns the memory of all the buffers. The mapped views
+        // cannot outlive the Accel, but a mapping may remain if a guard
+        // has been leaked: unmap it before releasing the memory.
+        for (id, entry) in self.buffers.iter_mut() {
+            if entry.mapped.is_some() {
+                log::warn!("Buffer {} still mapped, unmapping", id);
+                if let Err(e) = entry.unmap(self.queue) {
+                    log::error!("Cannot unmap buffer {}: {}", id, e);
                 }
             }
-            let err = unsafe { cl_sys::clReleaseMemObject(*buffer) };
-            assert_eq!(err, cl_sys::CL_SUCCESS, "{}", error_text(err));
+        }
+        // the commands gated by pending user events would never run
+        self.fail_gates();
+        let err = unsafe { cl_sys::clFinish(self.queue) };
+        assert_eq!(err, cl_sys::CL_SUCCESS, "{}", error_text(err));
+        // the sub-buffers are released before their parents
+        for (id, entry) in self.buffers.iter().filter(|(_, e)| e.parent.is_some()) {
+            log::debug!("Free sub-buffer {}", id);
+            entry.release_mem();
+        }
+        for (id, entry) in self.buffers.iter().filter(|(_, e)| e.parent.is_none()) {
+            log::debug!("Free buffer {}", id);
+            entry.release_mem();
+            entry.free_host();
         }
         for (s, kernel) in self.kernels.iter() {
-            println!("Free kernel {}", s);
+            log::debug!("Free kernel {}", s);
             let err = unsafe { cl_sys::clReleaseKernel(*kernel) };
             assert_eq!(err, cl_sys::CL_SUCCESS, "{}", error_text(err));
         }
+        for (s, program) in self.programs.iter() {
+            log::debug!("Free program {}", s);
+            let err = unsafe { cl_sys::clReleaseProgram(program.program) };
+            assert_eq!(err, cl_sys::CL_SUCCESS, "{}", error_text(err));
+        }
 
-        println!("Free MiniCL env.");
+        log::debug!("Free MiniCL env.");
         let err = unsafe {
             cl_sys::clReleaseCommandQueue(self.queue)
-                | cl_sys::clReleaseProgram(self.program)
-                | cl_sys::clReleaseDevice(self.device)
+                | cl_sys::clReleaseDevice(self.device.id)
                 | cl_sys::clReleaseContext(self.context)
         };
         assert_eq!(err, cl_sys::CL_SUCCESS, "{}", error_text(err));
@@ -552,33 +831,31 @@ impl TrueArg for LocalBuffer {
     }
 }
 
-/// Pointer conversion for buffer. We provide additional
-/// checks for better safety: the buffer must be registered
-/// and not currently mapped to the host.
-impl TrueArg for *mut cl_sys::c_void {
-    fn true_arg(&self, dev: &Accel) -> Result<*const cl_sys::c_void, MCLError> {
-        let (buffer, _size, _szf, is_map, _layout) = dev.buffers.get(self).ok_or(MCLError::Other("Buffer not registered when setting arg".to_string()))?;
-        if *is_map {
-             return Err(MCLError::Other("Buffer is mapped on the host. Cannot set as kernel arg.".to_string()));
-        }
-        Ok(buffer as *const _ as *const cl_sys::c_void)
-    }
-    fn arg_size(&self) -> usize {
-        std::mem::size_of::<cl_sys::cl_mem>()
-    }
-}
-
-
 /// This macro helps to run a kernel the first time, by simplifying
 /// the definition of the kernel args.
 /// For the next calls, it is possible to use [run_kernel](Accel::run_kernel)
 /// if the args are not changed. But it is better to use this macro which recheck all args.
 /// The measured overhead is generally very very small.
+/// A wait list can be given after the args and a semicolon, as in
+/// [run_kernel_after](Accel::run_kernel_after):
+/// `kernel_set_args_and_run!(dev, "k", 64, 16, v, x; &[&event])`.
 /// # Safety
 /// Calling an OpenCL kernel is not safe. A bug in the C code of the kernel 
 /// can lead to a segfault for instance.
 #[macro_export]
 macro_rules! kernel_set_args_and_run {
+    ($dev: expr, $kname: expr, $globsize: expr, $locsize:expr, $($arg:expr),* ; $wait:expr) => {{
+        let mut count: i32 = -1;
+        $(
+            count +=1;
+            $dev.set_kernel_arg(& $kname, count as usize, & $arg)?;
+        )*
+        let (globsize, locsize) = ($globsize, $locsize);
+        let wait_list: &[&$crate::Event] = $wait;
+        let kname = & $kname;
+        let dev = &mut $dev;
+        unsafe { dev.run_kernel_after(kname, globsize, locsize, wait_list) }
+    }};
     ($dev: expr, $kname: expr, $globsize: expr, $locsize:expr, $($arg:expr),*) => {{
         // println!("Device={:?}", $dev);
         // println!("Kernel={:?}", $kname);
@@ -590,81 +867,13 @@ macro_rules! kernel_set_args_and_run {
             count +=1;
             $dev.set_kernel_arg(& $kname, count as usize, & $arg)?;
         )*
-        unsafe { $dev.run_kernel(& $kname, $globsize, $locsize) }
+        let (globsize, locsize) = ($globsize, $locsize);
+        let kname = & $kname;
+        let dev = &mut $dev;
+        unsafe { dev.run_kernel(kname, globsize, locsize) }
     }}
 }
 
-pub fn error_text(error_code: cl_sys::cl_int) -> &'static str {
-    match error_code {
-        cl_sys::CL_SUCCESS => "CL_SUCCESS",
-        cl_sys::CL_DEVICE_NOT_FOUND => "CL_DEVICE_NOT_FOUND",
-        cl_sys::CL_DEVICE_NOT_AVAILABLE => "CL_DEVICE_NOT_AVAILABLE",
-        cl_sys::CL_COMPILER_NOT_AVAILABLE => "CL_COMPILER_NOT_AVAILABLE",
-        cl_sys::CL_MEM_OBJECT_ALLOCATION_FAILURE => "CL_MEM_OBJECT_ALLOCATION_FAILURE",
-        cl_sys::CL_OUT_OF_RESOURCES => "CL_OUT_OF_RESOURCES",
-        cl_sys::CL_OUT_OF_HOST_MEMORY => "CL_OUT_OF_HOST_MEMORY",
-        cl_sys::CL_PROFILING_INFO_NOT_AVAILABLE => "CL_PROFILING_INFO_NOT_AVAILABLE",
-        cl_sys::CL_MEM_COPY_OVERLAP => "CL_MEM_COPY_OVERLAP",
-        cl_sys::CL_IMAGE_FORMAT_MISMATCH => "CL_IMAGE_FORMAT_MISMATCH",
-        cl_sys::CL_IMAGE_FORMAT_NOT_SUPPORTED => "CL_IMAGE_FORMAT_NOT_SUPPORTED",
-        cl_sys::CL_BUILD_PROGRAM_FAILURE => "CL_BUILD_PROGRAM_FAILURE",
-        cl_sys::CL_MAP_FAILURE => "CL_MAP_FAILURE",
-        cl_sys::CL_MISALIGNED_SUB_BUFFER_OFFSET => "CL_MISALIGNED_SUB_BUFFER_OFFSET",
-        cl_sys::CL_EXEC_STATUS_ERROR_FOR_EVENTS_IN_WAIT_LIST => {
-            "CL_EXEC_STATUS_ERROR_FOR_EVENTS_IN_WAIT_LIST"
-        }
-        cl_sys::CL_COMPILE_PROGRAM_FAILURE => "CL_COMPILE_PROGRAM_FAILURE",
-        cl_sys::CL_LINKER_NOT_AVAILABLE => "CL_LINKER_NOT_AVAILABLE",
-        cl_sys::CL_LINK_PROGRAM_FAILURE => "CL_LINK_PROGRAM_FAILURE",
-        cl_sys::CL_DEVICE_PARTITION_FAILED => "CL_DEVICE_PARTITION_FAILED",
-        cl_sys::CL_KERNEL_ARG_INFO_NOT_AVAILABLE => "CL_KERNEL_ARG_INFO_NOT_AVAILABLE",
-        cl_sys::CL_INVALID_VALUE => "CL_INVALID_VALUE",
-        cl_sys::CL_INVALID_DEVICE_TYPE => "CL_INVALID_DEVICE_TYPE",
-        cl_sys::CL_INVALID_PLATFORM => "CL_INVALID_PLATFORM",
-        cl_sys::CL_INVALID_DEVICE => "CL_INVALID_DEVICE",
-        cl_sys::CL_INVALID_CONTEXT => "CL_INVALID_CONTEXT",
-        cl_sys::CL_INVALID_QUEUE_PROPERTIES => "CL_INVALID_QUEUE_PROPERTIES",
-        cl_sys::CL_INVALID_COMMAND_QUEUE => "CL_INVALID_COMMAND_QUEUE",
-        cl_sys::CL_INVALID_HOST_PTR => "CL_INVALID_HOST_PTR",
-        cl_sys::CL_INVALID_MEM_OBJECT => "CL_INVALID_MEM_OBJECT",
-        cl_sys::CL_INVALID_IMAGE_FORMAT_DESCRIPTOR => "CL_INVALID_IMAGE_FORMAT_DESCRIPTOR",
-        cl_sys::CL_INVALID_IMAGE_SIZE => "CL_INVALID_IMAGE_SIZE",
-        cl_sys::CL_INVALID_SAMPLER => "CL_INVALID_SAMPLER",
-        cl_sys::CL_INVALID_BINARY => "CL_INVALID_BINARY",
-        cl_sys::CL_INVALID_BUILD_OPTIONS => "CL_INVALID_BUILD_OPTIONS",
-        cl_sys::CL_INVALID_PROGRAM => "CL_INVALID_PROGRAM",
-        cl_sys::CL_INVALID_PROGRAM_EXECUTABLE => "CL_INVALID_PROGRAM_EXECUTABLE",
-        cl_sys::CL_INVALID_KERNEL_NAME => "CL_INVALID_KERNEL_NAME",
-        cl_sys::CL_INVALID_KERNEL_DEFINITION => "CL_INVALID_KERNEL_DEFINITION",
-        cl_sys::CL_INVALID_KERNEL => "CL_INVALID_KERNEL",
-        cl_sys::CL_INVALID_ARG_INDEX => "CL_INVALID_ARG_INDEX",
-        cl_sys::CL_INVALID_ARG_VALUE => "CL_INVALID_ARG_VALUE",
-        cl_sys::CL_INVALID_ARG_SIZE => "CL_INVALID_ARG_SIZE",
-        cl_sys::CL_INVALID_KERNEL_ARGS => "CL_INVALID_KERNEL_ARGS",
-        cl_sys::CL_INVALID_WORK_DIMENSION => "CL_INVALID_WORK_DIMENSION",
-        cl_sys::CL_INVALID_WORK_GROUP_SIZE => "CL_INVALID_WORK_GROUP_SIZE",
-        cl_sys::CL_INVALID_WORK_ITEM_SIZE => "CL_INVALID_WORK_ITEM_SIZE",
-        cl_sys::CL_INVALID_GLOBAL_OFFSET => "CL_INVALID_GLOBAL_OFFSET",
-        cl_sys::CL_INVALID_EVENT_WAIT_LIST => "CL_INVALID_EVENT_WAIT_LIST",
-        cl_sys::CL_INVALID_EVENT => "CL_INVALID_EVENT",
-        cl_sys::CL_INVALID_OPERATION => "CL_INVALID_OPERATION",
-        cl_sys::CL_INVALID_GL_OBJECT => "CL_INVALID_GL_OBJECT",
-        cl_sys::CL_INVALID_BUFFER_SIZE => "CL_INVALID_BUFFER_SIZE",
-        cl_sys::CL_INVALID_MIP_LEVEL => "CL_INVALID_MIP_LEVEL",
-        cl_sys::CL_INVALID_GLOBAL_WORK_SIZE => "CL_INVALID_GLOBAL_WORK_SIZE",
-        cl_sys::CL_INVALID_PROPERTY => "CL_INVALID_PROPERTY",
-        cl_sys::CL_INVALID_IMAGE_DESCRIPTOR => "CL_INVALID_IMAGE_DESCRIPTOR",
-        cl_sys::CL_INVALID_COMPILER_OPTIONS => "CL_INVALID_COMPILER_OPTIONS",
-        cl_sys::CL_INVALID_LINKER_OPTIONS => "CL_INVALID_LINKER_OPTIONS",
-        cl_sys::CL_INVALID_DEVICE_PARTITION_COUNT => "CL_INVALID_DEVICE_PARTITION_COUNT",
-        cl_sys::CL_INVALID_PIPE_SIZE => "CL_INVALID_PIPE_SIZE",
-        cl_sys::CL_INVALID_DEVICE_QUEUE => "CL_INVALID_DEVICE_QUEUE",
-        cl_sys::CL_INVALID_GL_SHAREGROUP_REFERENCE_KHR => "CL_INVALID_GL_SHAREGROUP_REFERENCE_KHR",
-        cl_sys::CL_PLATFORM_NOT_FOUND_KHR => "CL_PLATFORM_NOT_FOUND_KHR",
-        _ => "UNKNOWN_ERROR",
-    }
-}
-
 // some unit tests
 #[test]
 fn test_init() -> Result<(), MCLError> {
@@ -678,6 +887,26 @@ fn test_init() -> Result<(), MCLError> {
     Ok(())
 }
 
+#[test]
+fn test_builder() -> Result<(), MCLError> {
+    let source = "__kernel  void simple_kernel(void){
+        int i = get_global_id(0);
+    }"
+    .to_string();
+
+    let builder = Accel::builder().filter(|d| d.device_type().is_ok());
+    let ndev = builder.matching_devices()?.len();
+    assert!(ndev > 0);
+    let dev = builder.device_index(ndev - 1).build(source)?;
+    let name = dev.device().name()?;
+    assert!(Accel::builder()
+        .device_name(&name)
+        .matching_devices()?
+        .iter()
+        .all(|d| d.name().unwrap().contains(&name)));
+    Ok(())
+}
+
 #[test]
 fn test_buffer() -> Result<(), MCLError> {
     let source = "__kernel  void simple_kernel(void){
@@ -690,7 +919,259 @@ fn test_buffer() -> Result<(), MCLError> {
     let v0 = v.clone();
     let v = dev.register_buffer(v)?;
     let v = dev.map_buffer(v)?;
-    assert_eq!(v0, v);
+    assert_eq!(&v0[..], &v[..]);
+    Ok(())
+}
+
+#[test]
+fn test_alloc() -> Result<(), MCLError> {
+    let mut dev = Accel::new("__kernel void k(void){}".to_string(), 0)?;
+    // buffer allocated by OpenCL
+    let w = dev.alloc_buffer_zeroed::<f32>(8, MemFlags::ALLOC_HOST_PTR)?;
+    assert_eq!(&dev.map_read(w)?[..], &[0.; 8][..]);
+    let v = dev.alloc_buffer::<i32>(4, MemFlags::READ_WRITE)?;
+    assert_eq!(v.len(), 4);
+    dev.write_buffer(&v, &[1, 2, 3, 4], 0)?;
+    assert_eq!(&dev.map_read(v)?[..], &[1, 2, 3, 4][..]);
+    Ok(())
+}
+
+#[test]
+fn test_host_flags() -> Result<(), MCLError> {
+    let mut dev = Accel::new("__kernel void k(void){}".to_string(), 0)?;
+    // access flags are enforced on the host side
+    let flags = MemFlags::READ_ONLY | MemFlags::HOST_NO_ACCESS;
+    let c = dev.register_buffer_with(vec![1.5f64; 4], flags)?;
+    assert!(matches!(dev.map_read(c), Err(MCLError::BufferAccess(_))));
+    let c = dev.alloc_buffer::<i32>(4, MemFlags::HOST_READ_ONLY)?;
+    assert!(matches!(
+        dev.write_buffer(&c, &[0; 4], 0),
+        Err(MCLError::BufferAccess(_))
+    ));
+    dev.map_read(c)?;
+    Ok(())
+}
+
+#[test]
+fn test_release() -> Result<(), MCLError> {
+    let mut dev = Accel::new("__kernel void k(void){}".to_string(), 0)?;
+    // release, or take back, single buffers
+    let tmp = dev.alloc_buffer::<i32>(4, MemFlags::READ_WRITE)?;
+    dev.write_buffer(&tmp, &[1, 2, 3, 4], 0)?;
+    assert_eq!(dev.into_vec(tmp)?, vec![1, 2, 3, 4]);
+    assert!(matches!(
+        dev.read_buffer(&tmp, &mut [0], 0),
+        Err(MCLError::BufferNotRegistered)
+    ));
+    let tmp = dev.register_buffer(vec![5u8; 3])?;
+    let tmp2 = dev.register_buffer(vec![6u8; 3])?;
+    dev.release_buffer(tmp)?;
+    assert!(matches!(
+        dev.release_buffer(tmp),
+        Err(MCLError::BufferNotRegistered)
+    ));
+    assert_eq!(dev.into_vec(tmp2)?, vec![6; 3]);
+    Ok(())
+}
+
+#[test]
+fn test_copy_fill() -> Result<(), MCLError> {
+    let mut dev = Accel::new("__kernel void k(void){}".to_string(), 0)?;
+    // copies and fills on the device
+    let a = dev.alloc_buffer::<f32>(8, MemFlags::READ_WRITE)?;
+    let b = dev.alloc_buffer::<f32>(8, MemFlags::READ_WRITE)?;
+    dev.fill_buffer(&a, 1.5, 0..8)?;
+    dev.fill_buffer(&a, -1.0, 6..8)?;
+    dev.fill_buffer(&b, 0.0, 0..8)?;
+    dev.copy_buffer(&a, &b, 4..8, 0)?;
+    assert_eq!(dev.into_vec(b)?, vec![1.5, 1.5, -1.0, -1.0, 0., 0., 0., 0.]);
+    dev.copy_buffer(&a, &a, 0..2, 2)?;
+    let err = dev.copy_buffer(&a, &a, 0..4, 2).unwrap_err();
+    assert_eq!(err.code(), Some(cl_sys::CL_MEM_COPY_OVERLAP));
+    assert!(matches!(
+        dev.fill_buffer(&a, 0.0, 4..9),
+        Err(MCLError::OutOfRange { .. })
+    ));
+    // elements whose size is not a power of two
+    let c = dev.alloc_buffer::<[u8; 3]>(4, MemFlags::READ_WRITE)?;
+    dev.fill_buffer(&c, [1, 2, 3], 0..4)?;
+    assert_eq!(dev.into_vec(c)?, vec![[1, 2, 3]; 4]);
+    // the fills do not need host access
+    let flags = MemFlags::READ_WRITE | MemFlags::HOST_NO_ACCESS;
+    let c = dev.alloc_buffer::<[u8; 3]>(9, flags)?;
+    let d = dev.alloc_buffer::<[u8; 3]>(9, MemFlags::READ_WRITE)?;
+    dev.fill_buffer(&c, [0; 3], 0..9)?;
+    dev.fill_buffer(&c, [4, 5, 6], 1..8)?;
+    dev.copy_buffer(&c, &d, 0..9, 0)?;
+    let mut expected = vec![[4, 5, 6]; 9];
+    expected[0] = [0; 3];
+    expected[8] = [0; 3];
+    assert_eq!(dev.into_vec(d)?, expected);
+    let e = dev.alloc_buffer::<f32>(4, flags)?;
+    dev.fill_buffer(&e, 2.5, 0..4)?;
+    Ok(())
+}
+
+#[test]
+fn test_transfer() -> Result<(), MCLError> {
+    let mut dev = Accel::new("__kernel void k(void){}".to_string(), 0)?;
+    let v = dev.register_buffer(vec![3i32; 16])?;
+    // partial transfers
+    dev.write_buffer(&v, &[1, 2, 3], 13)?;
+    let mut tail = [0; 4];
+    dev.read_buffer(&v, &mut tail, 12)?;
+    assert_eq!(tail, [3, 1, 2, 3]);
+    assert!(matches!(
+        dev.read_buffer(&v, &mut tail, 14),
+        Err(MCLError::OutOfRange { .. })
+    ));
+    dev.write_buffer::<i32>(&v, &[], 16)?;
+    assert!(matches!(
+        dev.write_buffer(&v, &[0], 16),
+        Err(MCLError::OutOfRange { .. })
+    ));
+    assert_eq!(dev.into_vec(v)?[11..], [3, 3, 1, 2, 3]);
+    Ok(())
+}
+
+#[test]
+fn test_map() -> Result<(), MCLError> {
+    let mut dev = Accel::new("__kernel void k(void){}".to_string(), 0)?;
+    let v = dev.register_buffer(vec![3i32; 16])?;
+    assert_eq!(v.len(), 16);
+    let mut mapped = dev.map_buffer(v)?;
+    assert_eq!(&mapped[..], &[3; 16][..]);
+    mapped[0] = 5;
+    drop(mapped);
+    // the guard has unmapped the buffer: it can be mapped again
+    let mapped = dev.map_buffer(v)?;
+    assert_eq!(mapped[0], 5);
+    assert_eq!(mapped.buffer(), v);
+    mapped.unmap()?;
+
+    // write-only mapping then read-only mapping
+    let mut mapped = dev.map_write_invalidate(v)?;
+    mapped.iter_mut().for_each(|x| *x = 7);
+    drop(mapped);
+    let mapped = dev.map_read(v)?;
+    assert_eq!(&mapped[..], &[7; 16][..]);
+    mapped.unmap()?;
+
+    // a buffer can only be used by the Accel that registered it
+    let mut other = Accel::new("__kernel void k(void){}".to_string(), 0)?;
+    assert!(matches!(other.map_buffer(v), Err(MCLError::ForeignBuffer)));
+    Ok(())
+}
+
+#[test]
+fn test_buffer_layouts() -> Result<(), MCLError> {
+    // registers, maps, takes back and drops buffers of several
+    // element types and lengths
+    fn check<T: Clone + PartialEq + std::fmt::Debug>(
+        dev: &mut Accel,
+        value: T,
+    ) -> Result<(), MCLError> {
+        for &n in [0, 1, 3, 1000].iter() {
+            let mut v = Vec::with_capacity(2 * n + 1);
+            v.resize(n, value.clone());
+            // kept until the drop of the Accel
+            let kept = dev.register_buffer(v.clone())?;
+            assert_eq!(kept.len(), n);
+            let buf = dev.register_buffer(v.clone())?;
+            assert_eq!(&dev.map_read(buf)?[..], &v[..]);
+            assert_eq!(dev.into_vec(buf)?, v);
+            let buf = dev.register_buffer(v)?;
+            dev.release_buffer(buf)?;
+        }
+        Ok(())
+    }
+
+    let source = "__kernel void k(__global int *v){}".to_string();
+    let mut dev = Accel::new(source, 0)?;
+    check(&mut dev, 7u8)?;
+    check(&mut dev, -7i16)?;
+    check(&mut dev, 7u32)?;
+    check(&mut dev, 7.5f64)?;
+    check(&mut dev, [1.5f32, 2.5, 3.5])?;
+    check(&mut dev, (1u8, 2u64))?;
+    check(&mut dev, ())?;
+
+    // empty buffers are passed to the kernels as null pointers
+    dev.register_kernel("k")?;
+    let empty = dev.register_buffer(Vec::<i32>::new())?;
+    dev.set_kernel_arg("k", 0, &empty)?;
+    let empty = dev.alloc_buffer_zeroed::<i32>(0, MemFlags::READ_WRITE)?;
+    dev.write_buffer(&empty, &[], 0)?;
+    assert!(dev.map_buffer(empty)?.is_empty());
+    drop(dev);
+    Ok(())
+}
+
+#[test]
+fn test_sub_buffer() -> Result<(), MCLError> {
+    let source = "__kernel  void simple_add(__global int *v, int x){
+        int i = get_global_id(0);
+        v[i] += x;
+    }"
+    .to_string();
+    let mut dev = Accel::new(source, 0)?;
+    dev.register_kernel("simple_add")?;
+    // alignment of the sub-buffers, in elements
+    let align = (dev.device().mem_base_addr_align()? as usize / 8 / 4).max(1);
+    let n = 4 * align;
+    let buf = dev.register_buffer((0..n as i32).collect())?;
+    let sub = dev.sub_buffer(&buf, align..2 * align)?;
+    assert_eq!(sub.len(), align);
+    kernel_set_args_and_run!(dev, "simple_add", align, 1, sub, 1000i32)?;
+    let v = dev.map_read(buf)?;
+    assert_eq!(v[align - 1], align as i32 - 1);
+    assert_eq!(v[align], align as i32 + 1000);
+    assert_eq!(v[2 * align], 2 * align as i32);
+    drop(v);
+
+    // sub-buffers of sub-buffers are views on the same memory
+    let subsub = dev.sub_buffer(&sub, 0..1)?;
+    let mut first = [0];
+    dev.read_buffer(&subsub, &mut first, 0)?;
+    assert_eq!(first, [align as i32 + 1000]);
+    assert!(matches!(
+        dev.sub_buffer(&sub, 0..align + 1),
+        Err(MCLError::OutOfRange { .. })
+    ));
+    if align > 1 {
+        let err = dev.sub_buffer(&buf, 1..2).unwrap_err();
+        assert_eq!(err.code(), Some(cl_sys::CL_MISALIGNED_SUB_BUFFER_OFFSET));
+    }
+
+    // the parent cannot be released before its sub-buffers
+    assert!(matches!(
+        dev.release_buffer(buf),
+        Err(MCLError::BufferHasSubBuffers)
+    ));
+    // a mapping blocks the overlapping buffers only
+    let other = dev.sub_buffer(&buf, 2 * align..3 * align)?;
+    std::mem::forget(dev.map_buffer(sub)?);
+    assert!(matches!(
+        dev.set_kernel_arg("simple_add", 0, &buf),
+        Err(MCLError::BufferMapped)
+    ));
+    dev.set_kernel_arg("simple_add", 0, &other)?;
+    Ok(())
+}
+
+#[test]
+fn test_leaked_mapping() -> Result<(), MCLError> {
+    let source = "__kernel void k(void){}".to_string();
+    let mut dev = Accel::new(source, 0)?;
+    let v = dev.register_buffer(vec![1u64; 1024])?;
+    let w = dev.alloc_buffer::<u64>(1024, MemFlags::READ_WRITE)?;
+    // a leaked guard leaves the buffer mapped
+    std::mem::forget(dev.map_buffer(v)?);
+    std::mem::forget(dev.map_read(w)?);
+    assert!(matches!(dev.map_buffer(v), Err(MCLError::BufferMapped)));
+    assert!(matches!(dev.release_buffer(w), Err(MCLError::BufferMapped)));
+    // the drop unmaps the buffers before freeing the memory
+    drop(dev);
     Ok(())
 }
 
@@ -711,6 +1192,307 @@ fn test_kernel() -> Result<(), MCLError> {
     let x = 3;
     kernel_set_args_and_run!(dev, kernel_name, 16, 4, v, x)?;
     let v = dev.map_buffer(v)?;
-    assert_eq!(vp, v);
+    assert_eq!(&vp[..], &v[..]);
+    Ok(())
+}
+
+#[test]
+fn test_ndrange() -> Result<(), MCLError> {
+    let source = "__kernel void fill(__global int *v){
+        int i = get_global_id(0);
+        int j = get_global_id(1);
+        v[i + j * get_global_size(0)] = 100 * j + i;
+    }
+    __kernel void ids(__global int *v){
+        int i = get_global_id(0) - get_global_offset(0);
+        int j = get_global_id(1) - get_global_offset(1);
+        v[i + j * get_global_size(0)] = 100 * get_global_id(1) + get_global_id(0);
+    }"
+    .to_string();
+    let mut dev = Accel::new(source, 0)?;
+    dev.register_kernel("fill")?;
+    dev.register_kernel("ids")?;
+    let v = dev.register_buffer(vec![0i32; 8 * 4])?;
+    kernel_set_args_and_run!(dev, "fill", [8, 4], [4, 2], v)?;
+    let expected: Vec<i32> = (0..4).flat_map(|j| (0..8).map(move |i| 100 * j + i)).collect();
+    assert_eq!(&dev.map_read(v)?[..], &expected[..]);
+
+    // the offset shifts the global ids
+    let u = dev.register_buffer(vec![0i32; 4 * 2])?;
+    let global = NDRange::from([4, 2]).with_offset([3, 5])?;
+    kernel_set_args_and_run!(dev, "ids", global, [2, 2], u)?;
+    let expected: Vec<i32> = (5..7).flat_map(|j| (3..7).map(move |i| 100 * j + i)).collect();
+    assert_eq!(&dev.map_read(u)?[..], &expected[..]);
+
+    let w = dev.register_buffer(vec![0i32; 2 * 2 * 2])?;
+    let global = NDRange::from([2, 2, 2]).with_offset([1, 0, 0])?;
+    let err = kernel_set_args_and_run!(dev, "fill", global, [2, 2, 3], w).unwrap_err();
+    assert!(matches!(err, MCLError::InvalidWorkSize { .. }));
+    let max = dev.device().max_work_item_sizes()?;
+    let err = kernel_set_args_and_run!(dev, "fill", max[0] + 1, max[0] + 1, w).unwrap_err();
+    assert!(matches!(err, MCLError::WorkGroupTooLarge { .. }));
+    Ok(())
+}
+
+#[test]
+fn test_local_size() -> Result<(), MCLError> {
+    let source = "__kernel void fill(__global int *v, int n){
+        int i = get_global_id(0);
+        if (i >= n) return;
+        v[i] = i;
+    }"
+    .to_string();
+    let mut dev = Accel::new(source, 0)?;
+    dev.register_kernel("fill")?;
+    let n = 4095;
+    let v = dev.register_buffer(vec![-1i32; n])?;
+    // local size chosen by the driver
+    kernel_set_args_and_run!(dev, "fill", n, None, v, n as i32)?;
+    let expected: Vec<i32> = (0..n as i32).collect();
+    assert_eq!(&dev.map_read(v)?[..], &expected[..]);
+
+    let local = dev.preferred_local_size("fill", n)?;
+    assert!(!local.is_empty() && local.len() <= dev.device().max_work_group_size()?);
+    // the global size is padded and the true size is the argument 1
+    dev.fill_buffer(&v, -1, 0..n)?;
+    dev.pad_global_size("fill", Some(1))?;
+    kernel_set_args_and_run!(dev, "fill", n, LocalSize::AUTO, v)?;
+    assert_eq!(&dev.map_read(v)?[..], &expected[..]);
+    kernel_set_args_and_run!(dev, "fill", n, 16, v)?;
+    let too_large = i32::MAX as usize + 1;
+    let err = kernel_set_args_and_run!(dev, "fill", too_large, 16, v).unwrap_err();
+    assert!(matches!(err, MCLError::GlobalSizeOverflow { .. }));
+    // the size arguments must exist
+    dev.pad_global_size("fill", Some(2))?;
+    let err = kernel_set_args_and_run!(dev, "fill", n, 16, v).unwrap_err();
+    assert!(matches!(err, MCLError::PaddedSizeArgs { first: 2, dim: 1, num_args: 2, .. }));
+    dev.pad_global_size("fill", None)?;
+    kernel_set_args_and_run!(dev, "fill", 4096, 16, v, n as i32)?;
+    Ok(())
+}
+
+#[test]
+fn test_async() -> Result<(), MCLError> {
+    let source = "__kernel  void simple_add(__global int *v, int x){
+        int i = get_global_id(0);
+        v[i] += x;
+    }"
+    .to_string();
+    let mut dev = Accel::new(source, 0)?;
+    dev.register_kernel("simple_add")?;
+    let v = dev.register_buffer(vec![0i32; 64])?;
+    for _ in 0..100 {
+        kernel_set_args_and_run!(dev, "simple_add", 64, 16, v, 1i32)?;
+    }
+    dev.flush()?;
+    let event = kernel_set_args_and_run!(dev, "simple_add", 64, 16, v, 1i32)?;
+    event.wait()?;
+    // the maps wait for the enqueued kernels
+    kernel_set_args_and_run!(dev, "simple_add", 64, 16, v, 1i32)?;
+    assert_eq!(&dev.map_read(v)?[..], &[102; 64][..]);
+
+    dev.set_blocking(true);
+    kernel_set_args_and_run!(dev, "simple_add", 64, 16, v, 1i32)?;
+    dev.finish()?;
+    assert_eq!(&dev.map_read(v)?[..], &[103; 64][..]);
+    Ok(())
+}
+
+#[test]
+fn test_events() -> Result<(), MCLError> {
+    let source = "__kernel  void simple_add(__global int *v, int x){
+        int i = get_global_id(0);
+        v[i] += x;
+    }"
+    .to_string();
+    let mut dev = Accel::new(source, 0)?;
+    dev.register_kernel("simple_add")?;
+    let v = dev.register_buffer(vec![0i32; 64])?;
+    let w = dev.alloc_buffer::<i32>(64, MemFlags::READ_WRITE)?;
+
+    // the kernel is gated by the host
+    let ready = dev.user_event()?;
+    assert_eq!(ready.status()?, EventStatus::Submitted);
+    let run = kernel_set_args_and_run!(dev, "simple_add", 64, 16, v, 2i32; &[&ready])?;
+    dev.flush()?;
+    assert!(!run.is_complete()?);
+    let copy = dev.copy_buffer_after(&v, &w, 0..64, 0, &[&run])?;
+    ready.set_complete()?;
+    copy.wait()?;
+    assert!(run.is_complete()?);
+    assert_eq!(copy.clone().status()?, EventStatus::Complete);
+    assert_eq!(dev.map_read_after(w, &[&copy])?.to_vec(), vec![2; 64]);
+
+    // the user event is completed by another thread; the blocking mode
+    // does not wait for the gated commands
+    dev.set_blocking(true);
+    let ready = dev.user_event()?;
+    let run = kernel_set_args_and_run!(dev, "simple_add", 64, 16, v, 1i32; &[&ready])?;
+    let copy = dev.copy_buffer_after(&v, &w, 0..32, 32, &[&run])?;
+    assert!(!copy.is_complete()?);
+    let thread = std::thread::spawn(move || ready.set_complete());
+    let mut head = [0; 2];
+    dev.read_buffer_after(&v, &mut head, 0, &[&copy])?;
+    thread.join().unwrap()?;
+    assert_eq!(head, [3, 3]);
+    dev.write_buffer_after(&w, &[7; 32], 0, &[])?.wait()?;
+    dev.set_blocking(false);
+    let mut expected = vec![7; 32];
+    expected.resize(64, 3);
+    assert_eq!(dev.map_read(w)?.to_vec(), expected);
+
+    // the blocking calls wait until another thread completes the user event
+    let ready = dev.user_event()?;
+    kernel_set_args_and_run!(dev, "simple_add", 64, 16, v, 1i32; &[&ready])?;
+    let thread = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        ready.set_complete()
+    });
+    assert_eq!(dev.into_vec(v)?, vec![4; 64]);
+    thread.join().unwrap()?;
+
+    // a failed user event aborts the commands waiting for it
+    let ready = dev.user_event()?;
+    assert!(matches!(ready.set_failed(0), Err(MCLError::UserEventStatus(0))));
+    let fill = dev.fill_buffer_after(&w, 5, 0..64, &[&ready])?;
+    ready.set_failed(-1)?;
+    assert!(fill.wait().is_err());
+    assert!(matches!(fill.status()?, EventStatus::Failed(_)));
+    // as well as a user event dropped before its completion
+    let ready = dev.user_event()?;
+    let fill = dev.fill_buffer_after(&w, 5, 0..64, &[&ready])?;
+    drop(ready);
+    assert!(fill.wait().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_errors() -> Result<(), MCLError> {
+    let source = "__kernel  void simple_kernel(void){
+        int i = get_global_id(0);
+    }"
+    .to_string();
+
+    let mut dev = Accel::new(source, 0)?;
+    let err = dev.register_kernel("no_kernel").unwrap_err();
+    assert_eq!(err.code(), Some(cl_sys::CL_INVALID_KERNEL_NAME));
+    assert!(err.to_string().contains("no_kernel"));
+    dev.register_kernel("simple_kernel")?;
+    assert!(matches!(
+        dev.register_kernel("simple_kernel"),
+        Err(MCLError::KernelAlreadyRegistered(_))
+    ));
+    assert!(matches!(
+        dev.set_kernel_arg("other_kernel", 0, &1i32),
+        Err(MCLError::KernelNotFound(_))
+    ));
+
+    let source = "__kernel  void bad_kernel(void){\n undeclared = 1;\n}".to_string();
+    match Accel::new(source, 0) {
+        Err(MCLError::BuildFailed {
+            log, diagnostics, ..
+        }) => {
+            assert!(log.contains("undeclared"));
+            assert!(diagnostics
+                .iter()
+                .any(|d| d.severity == "error" && d.line == 2));
+        }
+        _ => panic!("build failure expected"),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_programs() -> Result<(), MCLError> {
+    let source = "__kernel  void add(__global int *v, int x){
+        int i = get_global_id(0);
+        v[i] += x;
+    }"
+    .to_string();
+    let mut dev = Accel::new(source, 0)?;
+    dev.register_kernel("add")?;
+    let v = dev.register_buffer(vec![1i32; 16])?;
+    kernel_set_args_and_run!(dev, "add", 16, 4, v, 1i32)?;
+
+    // second program, compiled later, working on the same buffer
+    let source = "__kernel  void mul(__global int *v, int x){
+        int i = get_global_id(0);
+        v[i] *= x;
+    }"
+    .to_string();
+    dev.add_program("extra", source.clone(), &BuildOptions::new())?;
+    assert!(matches!(
+        dev.add_program("extra", source, &BuildOptions::new()),
+        Err(MCLError::ProgramAlreadyRegistered(_))
+    ));
+    assert!(matches!(
+        dev.register_kernel("mul"),
+        Err(MCLError::Cl { .. })
+    ));
+    dev.register_kernel_from("extra", "mul")?;
+    dev.program_build_log("extra")?;
+    kernel_set_args_and_run!(dev, "mul", 16, 4, v, 3i32)?;
+    let v = dev.map_buffer(v)?;
+    assert_eq!(&v[..], &[6; 16][..]);
+    Ok(())
+}
+
+#[test]
+fn test_cache() -> Result<(), MCLError> {
+    let source = "__kernel  void simple_add(__global int *v, int x){
+        int i = get_global_id(0);
+        v[i] += x;
+    }"
+    .to_string();
+    let dir = std::env::temp_dir().join(format!("minicl_cache_{}", std::process::id()));
+    let count = || std::fs::read_dir(&dir).map(|d| d.count()).unwrap_or(0);
+
+    let dev = Accel::builder().platform(0).cache_dir(&dir).build(source.clone())?;
+    assert_eq!(count(), 1);
+    drop(dev);
+    // the second build is loaded from the cache
+    let mut dev = Accel::builder().platform(0).cache_dir(&dir).build(source)?;
+    assert_eq!(count(), 1);
+    dev.register_kernel("simple_add")?;
+    let v = dev.register_buffer(vec![1i32; 16])?;
+    kernel_set_args_and_run!(dev, "simple_add", 16, 4, v, 2i32)?;
+    let v = dev.map_buffer(v)?;
+    assert_eq!(&v[..], &[3; 16][..]);
+    drop(v);
+
+    dev.clear_cache()?;
+    assert_eq!(count(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}
+
+#[test]
+fn test_cache_headers() -> Result<(), MCLError> {
+    let source = "#include \"value.h\"
+    __kernel  void set_value(__global int *v){
+        v[get_global_id(0)] = VALUE;
+    }"
+    .to_string();
+    let dir = std::env::temp_dir().join(format!("minicl_cache_headers_{}", std::process::id()));
+    let inc = dir.join("include");
+    std::fs::create_dir_all(&inc).unwrap();
+    let run = |value: i32| -> Result<Vec<i32>, MCLError> {
+        std::fs::write(inc.join("value.h"), format!("#define VALUE {}\n", value)).unwrap();
+        let mut dev = Accel::builder()
+            .platform(0)
+            .options(BuildOptions::new().include_dir(&inc))
+            .cache_dir(&dir)
+            .build(source.clone())?;
+        dev.register_kernel("set_value")?;
+        let v = dev.register_buffer(vec![0i32; 16])?;
+        kernel_set_args_and_run!(dev, "set_value", 16, 4, v)?;
+        dev.into_vec(v)
+    };
+    assert_eq!(run(1)?, vec![1; 16]);
+    // the edited header gives another cache entry
+    assert_eq!(run(2)?, vec![2; 16]);
+    assert_eq!(run(1)?, vec![1; 16]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
     Ok(())
 }
@@ -0,0 +1,256 @@
+//! Sizes of the kernel launches.
+use std::fmt;
+
+/// Global or local size of a kernel launch, in 1, 2 or 3 dimensions.
+/// It is built from a `usize` or an array of 1 to 3 `usize`.
+/// # Examples
+/// ```
+/// use minicl::NDRange;
+/// let global = NDRange::from([512, 256]).with_offset([1, 1])?;
+/// assert_eq!(global.dim(), 2);
+/// assert_eq!(global.sizes(), &[512, 256]);
+/// assert_eq!(global.offset(), &[1, 1]);
+/// assert_eq!(NDRange::from(64).len(), 64);
+/// # Ok::<(), minicl::MCLError>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NDRange {
+    dim: usize,
+    sizes: [usize; 3],
+    offset: [usize; 3],
+}
+
+impl NDRange {
+    /// Range of the given sizes (at most 3), with a zero offset.
+    fn new(sizes: &[usize]) -> Self {
+        let mut range = NDRange {
+            dim: sizes.len(),
+            sizes: [1; 3],
+            offset: [0; 3],
+        };
+        range.sizes[..sizes.len()].copy_from_slice(sizes);
+        range
+    }
+
+    /// Sets the global offset of the work-items (the value of
+    /// `get_global_offset` in the kernel).
+    /// The offset is ignored for the local size.
+    /// It must have the dimension of the sizes.
+    pub fn with_offset<O: Into<NDRange>>(mut self, offset: O) -> Result<Self, crate::MCLError> {
+        let offset = offset.into();
+        if offset.dim != self.dim {
+            return Err(crate::MCLError::OffsetDimension {
+                dim: self.dim,
+                offset_dim: offset.dim,
+            });
+        }
+        self.offset = offset.sizes;
+        Ok(self)
+    }
+
+    /// Number of dimensions (1, 2 or 3).
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Size in each dimension.
+    pub fn sizes(&self) -> &[usize] {
+        &self.sizes[..self.dim]
+    }
+
+    /// Offset in each dimension.
+    pub fn offset(&self) -> &[usize] {
+        &self.offset[..self.dim]
+    }
+
+    /// Total number of work-items.
+    pub fn len(&self) -> usize {
+        self.sizes().iter().product()
+    }
+
+    /// Returns true if there is no work-item.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Rounds each size up to a multiple of the local size.
+    /// The offset is unchanged.
+    pub fn round_up(&self, local: &NDRange) -> NDRange {
+        let mut range = *self;
+        for (size, &l) in range.sizes.iter_mut().zip(local.sizes()) {
+            if l > 0 {
+                *size = size.div_ceil(l) * l;
+            }
+        }
+        range
+    }
+}
+
+impl From<usize> for NDRange {
+    fn from(size: usize) -> Self {
+        NDRange::new(&[size])
+    }
+}
+
+impl From<[usize; 1]> for NDRange {
+    fn from(sizes: [usize; 1]) -> Self {
+        NDRange::new(&sizes)
+    }
+}
+
+impl From<[usize; 2]> for NDRange {
+    fn from(sizes: [usize; 2]) -> Self {
+        NDRange::new(&sizes)
+    }
+}
+
+impl From<[usize; 3]> for NDRange {
+    fn from(sizes: [usize; 3]) -> Self {
+        NDRange::new(&sizes)
+    }
+}
+
+impl fmt::Display for NDRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.dim == 1 {
+            write!(f, "{}", self.sizes[0])
+        } else {
+            write!(f, "{:?}", self.sizes())
+        }
+    }
+}
+
+/// Local size of a kernel launch: a fixed [NDRange], or automatic.
+/// It is built from the same types as [NDRange], or from an
+/// `Option<NDRange>` where `None` lets the OpenCL driver choose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalSize(pub Option<NDRange>);
+
+impl LocalSize {
+    /// The local size is chosen by the driver.
+    pub const AUTO: LocalSize = LocalSize(None);
+}
+
+impl<T: Into<NDRange>> From<T> for LocalSize {
+    fn from(size: T) -> Self {
+        LocalSize(Some(size.into()))
+    }
+}
+
+impl From<Option<NDRange>> for LocalSize {
+    fn from(size: Option<NDRange>) -> Self {
+        LocalSize(size)
+    }
+}
+
+/// Chooses a local size for a global size, from the limits of a kernel
+/// on a device. The first dimension gets the largest multiple of the
+/// preferred multiple, the other ones share the rest of the work-group
+/// with powers of two.
+pub(crate) fn auto_local_size(
+    global: &NDRange,
+    work_group_size: usize,
+    preferred_multiple: usize,
+    max_work_item_sizes: &[usize],
+) -> NDRange {
+    let multiple = preferred_multiple.max(1);
+    let mut budget = work_group_size.max(1);
+    let mut sizes = [1; 3];
+    for (d, size) in sizes.iter_mut().enumerate().take(global.dim) {
+        let max = max_work_item_sizes.get(d).copied().unwrap_or(1);
+        let limit = budget.min(max).max(1);
+        *size = if d == 0 {
+            // no need for more work-items than the (rounded) global size
+            let limit = limit.min(global.sizes[0].div_ceil(multiple) * multiple);
+            if limit >= multiple {
+                limit / multiple * multiple
+            } else {
+                limit.max(1)
+            }
+        } else {
+            let limit = limit.min(global.sizes[d].next_power_of_two());
+            // largest power of two not greater than the limit
+            1 << (usize::BITS - 1 - limit.leading_zeros())
+        };
+        budget /= *size;
+    }
+    NDRange {
+        dim: global.dim,
+        sizes,
+        offset: [0; 3],
+    }
+}
+
+/// Checks that the global size is a multiple of the local size,
+/// and that the local size is supported by the device.
+pub(crate) fn check_work_sizes(
+    global: &NDRange,
+    local: &NDRange,
+    max_work_item_sizes: &[usize],
+    max_work_group_size: usize,
+) -> Result<(), crate::MCLError> {
+    let compatible = global.dim == local.dim
+        && global
+            .sizes()
+            .iter()
+            .zip(local.sizes())
+            .all(|(&g, &l)| l > 0 && g.is_multiple_of(l));
+    if !compatible {
+        return Err(crate::MCLError::InvalidWorkSize {
+            global: *global,
+            local: *local,
+        });
+    }
+    let too_large = local.len() > max_work_group_size
+        || local
+            .sizes()
+            .iter()
+            .zip(max_work_item_sizes)
+            .any(|(l, max)| l > max);
+    if too_large {
+        return Err(crate::MCLError::WorkGroupTooLarge {
+            local: *local,
+            max_work_item_sizes: max_work_item_sizes.to_vec(),
+            max_work_group_size,
+        });
+    }
+    Ok(())
+}
+
+// some unit tests
+#[test]
+fn test_work_sizes() {
+    let max = [1024, 1024, 64];
+    assert!(check_work_sizes(&64.into(), &16.into(), &max, 1024).is_ok());
+    assert!(check_work_sizes(&[64, 32].into(), &[16, 16].into(), &max, 1024).is_ok());
+    assert!(check_work_sizes(&[64, 30].into(), &[16, 16].into(), &max, 1024).is_err());
+    assert!(check_work_sizes(&[64, 32].into(), &16.into(), &max, 1024).is_err());
+    assert!(check_work_sizes(&64.into(), &0.into(), &max, 1024).is_err());
+    assert!(check_work_sizes(&[8, 8, 128].into(), &[1, 1, 128].into(), &max, 1024).is_err());
+    assert!(check_work_sizes(&[64, 64].into(), &[64, 32].into(), &max, 1024).is_err());
+    assert_eq!(NDRange::from([4, 2, 3]).len(), 24);
+    assert_eq!(NDRange::from([4, 2]).to_string(), "[4, 2]");
+    assert!(NDRange::from([4, 2]).with_offset(1).is_err());
+}
+
+#[test]
+fn test_auto_local_size() {
+    let max = [1024, 1024, 64];
+    let local = auto_local_size(&4095.into(), 256, 32, &max);
+    assert_eq!(local.sizes(), &[256]);
+    assert_eq!(NDRange::from(4095).round_up(&local).sizes(), &[4096]);
+    let local = auto_local_size(&[40, 30].into(), 256, 32, &max);
+    assert_eq!(local.sizes(), &[64, 4]);
+    let local = auto_local_size(&[10, 3].into(), 256, 32, &max);
+    assert_eq!(local.sizes(), &[32, 4]);
+    let local = auto_local_size(&[4096, 4096, 100].into(), 1024, 64, &max);
+    assert_eq!(local.sizes(), &[1024, 1, 1]);
+    let local = auto_local_size(&7.into(), 1, 32, &max);
+    assert_eq!(local.sizes(), &[1]);
+    let global = NDRange::from([100, 10]).with_offset([5, 5]).unwrap();
+    let padded = global.round_up(&[16, 4].into());
+    assert_eq!(padded.sizes(), &[112, 12]);
+    assert_eq!(padded.offset(), &[5, 5]);
+    assert_eq!(LocalSize::from(None), LocalSize::AUTO);
+    assert_eq!(LocalSize::from([4, 4]).0.unwrap().sizes(), &[4, 4]);
+}
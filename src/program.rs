@@ -0,0 +1,362 @@
+//! Compilation of the OpenCL programs.
+use crate::{cache, check_cl_error, Device, MCLError};
+use std::fmt;
+use std::path::Path;
+
+/// Options passed to the OpenCL compiler.
+/// # Examples
+/// ```
+/// let options = minicl::BuildOptions::new()
+///     .define("NX", 512)
+///     .define_f32("DX", 0.5)
+///     .define_flag("TRANSPOSE")
+///     .fast_relaxed_math();
+/// assert_eq!(
+///     options.to_string(),
+///     "-D NX=512 -D DX=0.5f -D TRANSPOSE -cl-fast-relaxed-math"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildOptions {
+    options: Vec<String>,
+}
+
+impl BuildOptions {
+    /// Empty list of options.
+    pub fn new() -> Self {
+        BuildOptions::default()
+    }
+
+    /// Adds an option verbatim.
+    pub fn raw(mut self, option: &str) -> Self {
+        self.options.push(option.to_string());
+        self
+    }
+
+    /// Defines a preprocessor macro with a value (`-D name=value`).
+    pub fn define<V: fmt::Display>(self, name: &str, value: V) -> Self {
+        self.raw(&format!("-D {}={}", name, value))
+    }
+
+    /// Defines a preprocessor macro with a single precision value.
+    /// The value is written with the `f` suffix.
+    pub fn define_f32(self, name: &str, value: f32) -> Self {
+        self.raw(&format!("-D {}={:?}f", name, value))
+    }
+
+    /// Defines a preprocessor macro with a double precision value.
+    pub fn define_f64(self, name: &str, value: f64) -> Self {
+        self.raw(&format!("-D {}={:?}", name, value))
+    }
+
+    /// Defines a preprocessor macro without value (`-D name`).
+    pub fn define_flag(self, name: &str) -> Self {
+        self.raw(&format!("-D {}", name))
+    }
+
+    /// Adds a directory to the search path of the `#include` directives.
+    pub fn include_dir<P: AsRef<std::path::Path>>(self, dir: P) -> Self {
+        let dir = dir.as_ref().display().to_string();
+        if dir.contains(char::is_whitespace) {
+            self.raw(&format!("-I \"{}\"", dir))
+        } else {
+            self.raw(&format!("-I {}", dir))
+        }
+    }
+
+    /// Selects the OpenCL C version, e.g. `"CL2.0"`.
+    pub fn cl_std(self, version: &str) -> Self {
+        self.raw(&format!("-cl-std={}", version))
+    }
+
+    /// Allows optimizations that may violate IEEE 754 (`-cl-fast-relaxed-math`).
+    pub fn fast_relaxed_math(self) -> Self {
+        self.raw("-cl-fast-relaxed-math")
+    }
+
+    /// Allows `a * b + c` to be computed with reduced accuracy (`-cl-mad-enable`).
+    pub fn mad_enable(self) -> Self {
+        self.raw("-cl-mad-enable")
+    }
+
+    /// Ignores the signedness of zero (`-cl-no-signed-zeros`).
+    pub fn no_signed_zeros(self) -> Self {
+        self.raw("-cl-no-signed-zeros")
+    }
+
+    /// Assumes that arguments and results are not NaNs or infinities
+    /// (`-cl-finite-math-only`).
+    pub fn finite_math_only(self) -> Self {
+        self.raw("-cl-finite-math-only")
+    }
+
+    /// Disables all optimizations (`-cl-opt-disable`).
+    pub fn opt_disable(self) -> Self {
+        self.raw("-cl-opt-disable")
+    }
+
+    /// Suppresses all warnings (`-w`).
+    pub fn no_warnings(self) -> Self {
+        self.raw("-w")
+    }
+
+    /// Turns warnings into errors (`-Werror`).
+    pub fn werror(self) -> Self {
+        self.raw("-Werror")
+    }
+}
+
+impl fmt::Display for BuildOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.options.join(" "))
+    }
+}
+
+/// A message of the OpenCL compiler, parsed from the build log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildDiagnostic {
+    /// Source name given by the compiler (e.g. `<source>` or `program_source`).
+    pub file: String,
+    /// Line number in the source, starting from 1.
+    pub line: usize,
+    pub column: Option<usize>,
+    /// `error`, `warning` or `note`.
+    pub severity: String,
+    pub message: String,
+}
+
+/// Extracts the errors and warnings from a build log.
+/// The messages are expected in the `file:line:column: severity: message`
+/// format used by most OpenCL compilers (clang based).
+/// Lines that do not follow this format are ignored.
+pub fn parse_build_log(log: &str) -> Vec<BuildDiagnostic> {
+    let mut diagnostics = vec![];
+    for text in log.lines() {
+        for severity in ["error", "warning", "note"].iter() {
+            let pattern = format!(": {}: ", severity);
+            let pos = match text.find(&pattern) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let (head, last) = match split_number(&text[..pos]) {
+                Some(split) => split,
+                None => continue,
+            };
+            let (file, line, column) = match split_number(head) {
+                Some((file, line)) => (file, line, Some(last)),
+                None => (head, last, None),
+            };
+            diagnostics.push(BuildDiagnostic {
+                file: file.trim().to_string(),
+                line,
+                column,
+                severity: severity.to_string(),
+                message: text[pos + pattern.len()..].trim().to_string(),
+            });
+            break;
+        }
+    }
+    diagnostics
+}
+
+/// An OpenCL program, with the messages of its compilation.
+#[derive(Debug)]
+pub(crate) struct Program {
+    pub(crate) program: cl_sys::cl_program,
+    pub(crate) build_log: String,
+}
+
+/// Splits `text:number` into its text and number parts.
+fn split_number(text: &str) -> Option<(&str, usize)> {
+    let (head, tail) = text.rsplit_once(':')?;
+    Some((head, tail.trim().parse().ok()?))
+}
+
+/// Creates and builds a program from source.
+/// Returns the program and the build log. On failure the
+/// program is released.
+/// If a cache directory is given, the program binary is loaded from the
+/// cache when possible, and stored in the cache after a compilation.
+pub(crate) fn build_program(
+    context: cl_sys::cl_context,
+    device: &Device,
+    oclsource: &str,
+    options: &str,
+    cache_dir: Option<&Path>,
+) -> Result<(cl_sys::cl_program, String), MCLError> {
+    let device_name = device.name()?;
+    let cache_file = match cache_dir {
+        Some(dir) => Some(cache::cache_file(dir, device, oclsource, options)?),
+        None => None,
+    };
+    if let Some(binary) = cache_file.as_deref().and_then(cache::load) {
+        match build_from_binary(context, device.id, &device_name, &binary, options) {
+            Ok(built) => {
+                log::info!("Program binary loaded from the cache");
+                return Ok(built);
+            }
+            Err(e) => log::warn!("Cached program binary rejected, compiling the source: {}", e),
+        }
+    }
+
+    let mut err: i32 = 0;
+    let oclsource = std::ffi::CString::new(oclsource)?;
+    let program = unsafe {
+        cl_sys::clCreateProgramWithSource(
+            context,
+            1,
+            &(oclsource.as_ptr()) as *const *const cl_sys::libc::c_char,
+            std::ptr::null(),
+            &mut err,
+        )
+    };
+    check_cl_error(err, "clCreateProgramWithSource")?;
+
+    let result = build_and_log(program, device.id, &device_name, options);
+    if result.is_err() {
+        unsafe { cl_sys::clReleaseProgram(program) };
+    }
+    let build_log = result?;
+    if let Some(file) = &cache_file {
+        cache::store(file, program);
+    }
+    Ok((program, build_log))
+}
+
+/// Creates and builds a program from a binary.
+fn build_from_binary(
+    context: cl_sys::cl_context,
+    device: cl_sys::cl_device_id,
+    device_name: &str,
+    binary: &[u8],
+    options: &str,
+) -> Result<(cl_sys::cl_program, String), MCLError> {
+    let mut err: i32 = 0;
+    let mut status: i32 = 0;
+    let program = unsafe {
+        cl_sys::clCreateProgramWithBinary(
+            context,
+            1,
+            &device,
+            &binary.len(),
+            &binary.as_ptr(),
+            &mut status,
+            &mut err,
+        )
+    };
+    check_cl_error(err, "clCreateProgramWithBinary")?;
+    let result = check_cl_error(status, "clCreateProgramWithBinary")
+        .and_then(|()| build_and_log(program, device, device_name, options));
+    if result.is_err() {
+        unsafe { cl_sys::clReleaseProgram(program) };
+    }
+    result.map(|log| (program, log))
+}
+
+/// Builds a program and returns its build log.
+fn build_and_log(
+    program: cl_sys::cl_program,
+    device: cl_sys::cl_device_id,
+    device_name: &str,
+    options: &str,
+) -> Result<String, MCLError> {
+    let opt = std::ffi::CString::new(options)?;
+    let errb = unsafe {
+        cl_sys::clBuildProgram(program, 1, &device, opt.as_ptr(), None, std::ptr::null_mut())
+    };
+
+    // first get the size of the build log
+    let mut size = 0;
+    let err = unsafe {
+        cl_sys::clGetProgramBuildInfo(
+            program,
+            device,
+            cl_sys::CL_PROGRAM_BUILD_LOG,
+            0,
+            std::ptr::null_mut(),
+            &mut size,
+        )
+    };
+    check_cl_error(err, "clGetProgramBuildInfo")?; // We want to see this error even if build failed
+
+    log::debug!("Size of build log: {}", size);
+    // then get the build log
+    let mut build_log = vec![0u8; size];
+    let err = unsafe {
+        cl_sys::clGetProgramBuildInfo(
+            program,
+            device,
+            cl_sys::CL_PROGRAM_BUILD_LOG,
+            size,
+            build_log.as_mut_ptr() as *mut cl_sys::c_void,
+            std::ptr::null_mut(),
+        )
+    };
+    check_cl_error(err, "clGetProgramBuildInfo")?;
+    if let Some(end) = build_log.iter().position(|&b| b == 0) {
+        build_log.truncate(end);
+    }
+    let build_log = String::from_utf8_lossy(&build_log).into_owned();
+
+    if errb != cl_sys::CL_SUCCESS {
+        log::error!("Build messages:\n{}", build_log);
+    } else if !build_log.trim().is_empty() {
+        log::info!("Build messages:\n{}", build_log);
+    }
+
+    if errb == cl_sys::CL_BUILD_PROGRAM_FAILURE {
+        return Err(MCLError::BuildFailed {
+            device: device_name.to_string(),
+            options: options.to_string(),
+            diagnostics: parse_build_log(&build_log),
+            log: build_log,
+        });
+    }
+    check_cl_error(errb, "clBuildProgram")?;
+    Ok(build_log)
+}
+
+// some unit tests
+#[test]
+fn test_parse_build_log() {
+    let log = "<source>:3:9: error: use of undeclared identifier 'x'\n\
+               \x20       x = 1;\n\
+               \x20       ^\n\
+               program_source:12:1: warning: unused variable 'i'\n\
+               kernels.cl:7: error: expected ';'\n\
+               1 error generated.\n";
+    let diagnostics = parse_build_log(log);
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(
+        diagnostics[0],
+        BuildDiagnostic {
+            file: "<source>".to_string(),
+            line: 3,
+            column: Some(9),
+            severity: "error".to_string(),
+            message: "use of undeclared identifier 'x'".to_string(),
+        }
+    );
+    assert_eq!(diagnostics[1].file, "program_source");
+    assert_eq!(diagnostics[1].line, 12);
+    assert_eq!(diagnostics[1].severity, "warning");
+    assert_eq!(diagnostics[2].file, "kernels.cl");
+    assert_eq!(diagnostics[2].line, 7);
+    assert_eq!(diagnostics[2].column, None);
+}
+
+#[test]
+fn test_build_options() {
+    let options = BuildOptions::new()
+        .no_warnings()
+        .define("real", "double")
+        .define_f32("DT", 1e-7)
+        .define_f64("DX", 1.0)
+        .include_dir("my kernels")
+        .cl_std("CL2.0");
+    assert_eq!(
+        options.to_string(),
+        "-w -D real=double -D DT=1e-7f -D DX=1.0 -I \"my kernels\" -cl-std=CL2.0"
+    );
+    assert_eq!(BuildOptions::new().to_string(), "");
+}
@@ -0,0 +1,402 @@
+//! Generation of OpenCL sources from templates.
+//!
+//! A [KernelSource] replaces the `${name}` placeholders of an OpenCL
+//! source by typed values, and adds a header with the `#define`
+//! flags and the `real` floating point type. The header does not
+//! shift the line numbers reported by the compiler.
+//!
+//! A [SourceLoader] reads OpenCL files and resolves their `#include`
+//! directives on the Rust side, for drivers with a poor include support.
+//! # Examples
+//! ```
+//! use minicl::{KernelSource, Precision};
+//! let source = KernelSource::new("__kernel void scale(__global real *v){
+//!     int i = get_global_id(0);
+//!     if (i < ${n}) v[i] *= ${factor};
+//! }")
+//! .precision(Precision::Double)
+//! .set("n", 1000)
+//! .set_real("factor", 0.5)
+//! .render()?;
+//! assert!(source.contains("typedef double real;"));
+//! assert!(source.contains("if (i < 1000) v[i] *= 0.5;"));
+//! # Ok::<(), minicl::MCLError>(())
+//! ```
+use crate::MCLError;
+use std::path::{Path, PathBuf};
+
+/// Floating point precision of the `real` type in the OpenCL source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    Single,
+    Double,
+}
+
+/// A value that can be written in an OpenCL source.
+pub trait SourceValue {
+    /// Returns the OpenCL literal of the value.
+    fn to_source(&self) -> String;
+}
+
+macro_rules! int_source_value {
+    ($($t:ty),*) => {
+        $(impl SourceValue for $t {
+            fn to_source(&self) -> String {
+                self.to_string()
+            }
+        })*
+    }
+}
+
+int_source_value!(i32, u32, i64, u64, usize);
+
+/// Single precision literal, with the `f` suffix.
+impl SourceValue for f32 {
+    fn to_source(&self) -> String {
+        format!("{:?}f", self)
+    }
+}
+
+/// Double precision literal.
+impl SourceValue for f64 {
+    fn to_source(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+/// Booleans are written as `1` or `0`.
+impl SourceValue for bool {
+    fn to_source(&self) -> String {
+        if *self { "1" } else { "0" }.to_string()
+    }
+}
+
+/// OpenCL source template.
+#[derive(Debug, Clone)]
+pub struct KernelSource {
+    template: String,
+    precision: Option<Precision>,
+    flags: Vec<String>,
+    params: Vec<(String, String, bool)>,
+}
+
+impl KernelSource {
+    /// Template from an OpenCL source with `${name}` placeholders.
+    pub fn new<S: Into<String>>(template: S) -> Self {
+        KernelSource {
+            template: template.into(),
+            precision: None,
+            flags: vec![],
+            params: vec![],
+        }
+    }
+
+    /// Template from an OpenCL file. The `#include` directives are resolved
+    /// relative to the file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, MCLError> {
+        Ok(KernelSource::new(SourceLoader::new().load(path)?))
+    }
+
+    /// Defines the `real` type as `float` or `double`.
+    /// For double precision, the `cl_khr_fp64` extension is enabled.
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.precision = Some(precision);
+        self
+    }
+
+    /// Sets the value of the `${name}` placeholder.
+    pub fn set<V: SourceValue>(mut self, name: &str, value: V) -> Self {
+        self.params.retain(|(n, _, _)| n != name);
+        self.params.push((name.to_string(), value.to_source(), false));
+        self
+    }
+
+    /// Sets the `${name}` placeholder to a floating point value,
+    /// written with the suffix matching the [precision](KernelSource::precision).
+    pub fn set_real(mut self, name: &str, value: f64) -> Self {
+        self.params.retain(|(n, _, _)| n != name);
+        self.params.push((name.to_string(), value.to_source(), true));
+        self
+    }
+
+    /// Adds a `#define name` line at the beginning of the source if `enabled`.
+    pub fn flag(mut self, name: &str, enabled: bool) -> Self {
+        self.flags.retain(|f| f != name);
+        if enabled {
+            self.flags.push(name.to_string());
+        }
+        self
+    }
+
+    /// Generates the OpenCL source. All the placeholders must have a value.
+    pub fn render(&self) -> Result<String, MCLError> {
+        let mut source = String::new();
+        match self.precision {
+            Some(Precision::Single) => source.push_str("typedef float real;\n"),
+            Some(Precision::Double) => {
+                source.push_str("#pragma OPENCL EXTENSION cl_khr_fp64 : enable\n");
+                source.push_str("typedef double real;\n");
+            }
+            None => {}
+        }
+        for flag in self.flags.iter() {
+            source.push_str(&format!("#define {}\n", flag));
+        }
+        // keep the line numbers of the template in the compiler messages
+        if !source.is_empty() {
+            source.push_str("#line 1\n");
+        }
+
+        let mut used = vec![false; self.params.len()];
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find("${") {
+            source.push_str(&rest[..start]);
+            let offset = self.template.len() - rest.len() + start;
+            let line = self.template[..offset].matches('\n').count() + 1;
+            let end = rest[start..].find('}').ok_or_else(|| {
+                MCLError::UnresolvedPlaceholder {
+                    name: rest[start + 2..].chars().take(20).collect(),
+                    line,
+                }
+            })?;
+            let name = &rest[start + 2..start + end];
+            let index = self
+                .params
+                .iter()
+                .position(|(n, _, _)| n == name)
+                .ok_or_else(|| MCLError::UnresolvedPlaceholder {
+                    name: name.to_string(),
+                    line,
+                })?;
+            used[index] = true;
+            let (_, value, real) = &self.params[index];
+            source.push_str(value);
+            if *real && self.precision != Some(Precision::Double) {
+                source.push('f');
+            }
+            rest = &rest[start + end + 1..];
+        }
+        source.push_str(rest);
+
+        for ((name, _, _), used) in self.params.iter().zip(used) {
+            if !used {
+                log::warn!("Parameter '{}' not used in the OpenCL source", name);
+            }
+        }
+        Ok(source)
+    }
+}
+
+/// Reader of OpenCL source files.
+///
+/// The `#include "file"` and `#include <file>` directives are replaced by
+/// the content of the file, searched first in the directory of the including
+/// file (only for the quoted form), then in the include directories.
+/// `#line` directives are inserted, so that the compiler messages refer to
+/// the original files and lines.
+#[derive(Debug, Clone, Default)]
+pub struct SourceLoader {
+    include_dirs: Vec<PathBuf>,
+}
+
+impl SourceLoader {
+    pub fn new() -> Self {
+        SourceLoader::default()
+    }
+
+    /// Adds a directory to the search path of the `#include` directives.
+    pub fn include_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.include_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Reads an OpenCL file and resolves its includes.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<String, MCLError> {
+        let mut source = String::new();
+        let mut stack = vec![];
+        let mut once = vec![];
+        self.load_rec(path.as_ref(), &mut stack, &mut once, &mut source)?;
+        Ok(source)
+    }
+
+    /// Reads several OpenCL files and concatenates them.
+    pub fn load_all<P: AsRef<Path>>(&self, paths: &[P]) -> Result<String, MCLError> {
+        let mut source = String::new();
+        let mut once = vec![];
+        for path in paths {
+            let mut stack = vec![];
+            self.load_rec(path.as_ref(), &mut stack, &mut once, &mut source)?;
+        }
+        Ok(source)
+    }
+
+    fn load_rec(
+        &self,
+        path: &Path,
+        stack: &mut Vec<PathBuf>,
+        once: &mut Vec<PathBuf>,
+        source: &mut String,
+    ) -> Result<(), MCLError> {
+        let id = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if once.contains(&id) {
+            return Ok(());
+        }
+        let text = std::fs::read_to_string(path).map_err(|error| MCLError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        stack.push(id.clone());
+
+        let name = path.display().to_string().replace('\\', "/");
+        source.push_str(&format!("#line 1 \"{}\"\n", name));
+        for (num, line) in text.lines().enumerate() {
+            let trimmed = line.trim_start();
+            if is_pragma_once(trimmed) {
+                once.push(id.clone());
+                source.push('\n');
+                continue;
+            }
+            let include = match parse_include(trimmed) {
+                Some(include) => include,
+                None => {
+                    source.push_str(line);
+                    source.push('\n');
+                    continue;
+                }
+            };
+            let error = |message: String| MCLError::Source {
+                file: path.to_path_buf(),
+                line: num + 1,
+                message,
+            };
+            let (file, quoted) = include.map_err(error)?;
+            let found = self.find_include(path, file, quoted).ok_or_else(|| {
+                error(format!("included file '{}' not found", file))
+            })?;
+            let found_id = found.canonicalize().unwrap_or_else(|_| found.clone());
+            if stack.contains(&found_id) {
+                return Err(error(format!("recursive inclusion of '{}'", file)));
+            }
+            self.load_rec(&found, stack, once, source)?;
+            source.push_str(&format!("#line {} \"{}\"\n", num + 2, name));
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    /// Files included by a source, directly or not, that are found in the
+    /// include directories. The program cache hashes them, since the
+    /// driver reads them when compiling the source.
+    pub(crate) fn included_files(&self, source: &str) -> Vec<PathBuf> {
+        let mut files = vec![];
+        self.collect_includes(Path::new(""), source, &mut files);
+        files
+    }
+
+    fn collect_includes(&self, from: &Path, text: &str, files: &mut Vec<PathBuf>) {
+        for line in text.lines() {
+            let found = match parse_include(line.trim_start()) {
+                Some(Ok((file, quoted))) => self.find_include(from, file, quoted),
+                _ => None,
+            };
+            if let Some(found) = found {
+                if !files.contains(&found) {
+                    files.push(found.clone());
+                    if let Ok(text) = std::fs::read_to_string(&found) {
+                        self.collect_includes(&found, &text, files);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Searches an included file.
+    fn find_include(&self, from: &Path, file: &str, quoted: bool) -> Option<PathBuf> {
+        let local = from.parent().map(|dir| dir.join(file));
+        let local = if quoted { local } else { None };
+        local
+            .into_iter()
+            .chain(self.include_dirs.iter().map(|dir| dir.join(file)))
+            .find(|path| path.is_file())
+    }
+}
+
+/// Returns true for a `#pragma once` directive.
+fn is_pragma_once(line: &str) -> bool {
+    line.strip_prefix('#')
+        .and_then(|l| l.trim_start().strip_prefix("pragma"))
+        .is_some_and(|l| l.trim() == "once")
+}
+
+/// Parses an `#include` directive. Returns the file name and true if
+/// the name is between quotes, or None if the line is not an include.
+fn parse_include(line: &str) -> Option<Result<(&str, bool), String>> {
+    let rest = line.strip_prefix('#')?.trim_start().strip_prefix("include")?;
+    let rest = rest.trim();
+    let (close, quoted) = match rest.chars().next() {
+        Some('"') => ('"', true),
+        Some('<') => ('>', false),
+        _ => return Some(Err(format!("malformed #include directive '{}'", line))),
+    };
+    match rest[1..].find(close) {
+        Some(end) => Some(Ok((&rest[1..end + 1], quoted))),
+        None => Some(Err(format!("malformed #include directive '{}'", line))),
+    }
+}
+
+// some unit tests
+#[test]
+fn test_kernel_source() -> Result<(), MCLError> {
+    let template = "#define NX ${nx}\n#define DX ${dx}\n#define real_part ${x}\nreal v = ${dt};";
+    let source = KernelSource::new(template)
+        .precision(Precision::Single)
+        .set("nx", 512usize)
+        .set("dx", 0.25f32)
+        .set("x", true)
+        .set_real("dt", 0.5)
+        .flag("TRANSPOSE", true)
+        .flag("PERMUT", false)
+        .render()?;
+    assert_eq!(
+        source,
+        "typedef float real;\n#define TRANSPOSE\n#line 1\n\
+         #define NX 512\n#define DX 0.25f\n#define real_part 1\nreal v = 0.5f;"
+    );
+
+    let missing = KernelSource::new("int a;\nint b = ${b};").render();
+    match missing {
+        Err(MCLError::UnresolvedPlaceholder { name, line }) => {
+            assert_eq!(name, "b");
+            assert_eq!(line, 2);
+        }
+        _ => panic!("unresolved placeholder expected"),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_source_loader() -> Result<(), MCLError> {
+    let dir = std::env::temp_dir().join(format!("minicl_loader_{}", std::process::id()));
+    let inc = dir.join("inc");
+    std::fs::create_dir_all(&inc).unwrap();
+    std::fs::write(dir.join("main.cl"), "#include \"common.h\"\n#include <lib.h>\nint main_x;\n").unwrap();
+    std::fs::write(dir.join("common.h"), "#pragma once\nint common_x;\n").unwrap();
+    std::fs::write(inc.join("lib.h"), "#include \"common.h\"\nint lib_x;\n").unwrap();
+    std::fs::write(dir.join("bad.cl"), "int a;\n#include \"missing.h\"\n").unwrap();
+
+    let loader = SourceLoader::new().include_dir(&inc).include_dir(&dir);
+    let source = loader.load(dir.join("main.cl"))?;
+    assert_eq!(source.matches("int common_x;").count(), 1);
+    assert!(source.contains("int lib_x;"));
+    assert!(source.contains("#line 3 \""));
+
+    match loader.load(dir.join("bad.cl")) {
+        Err(MCLError::Source { file, line, .. }) => {
+            assert_eq!(file, dir.join("bad.cl"));
+            assert_eq!(line, 2);
+        }
+        _ => panic!("missing include expected"),
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+    Ok(())
+}